@@ -0,0 +1,583 @@
+//! Stand-in for the Method API, for running imports without sandbox keys. Point the API or
+//! payroll-cli at it with METHOD_BASE_URL=http://localhost:3002
+//!
+//! Everything is kept in memory and lost when it stops. Payments move from pending to
+//! processing to sent as they age, `SIM_STATUS_STEP_SECS` apart
+
+use axum::body::Bytes;
+use axum::extract::{Path, Query};
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, Utc};
+use log::{info, warn};
+use method_assesment::entities::account::DestAccount;
+use method_assesment::entities::account_response::{AccountResponse, Liability};
+use method_assesment::entities::entity::Entity;
+use method_assesment::entities::entity_response::EntityResponse;
+use method_assesment::entities::payment::Payment;
+use method_assesment::entities::payment_response::PaymentResponse;
+use method_assesment::utility::env::{parse_or, positive_or};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const ID_CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Method's payment statuses in the order a successful payment goes through them
+const PAYMENT_STATUSES: [&str; 3] = ["pending", "processing", "sent"];
+
+type Response = (StatusCode, Json<Value>);
+
+/// Paging and filters taken by the list endpoints, as Method takes them
+#[derive(Debug, Deserialize)]
+struct ListParams {
+    page: Option<usize>,
+    page_limit: Option<usize>,
+    from_date: Option<String>, // YYYY-MM-DD
+    to_date: Option<String>,
+}
+
+#[derive(Default)]
+struct Sim {
+    next_id: u64,
+    entities: Vec<EntityResponse>,
+    accounts: Vec<AccountResponse>,
+    payments: Vec<(DateTime<Utc>, PaymentResponse)>,
+    idempotency_keys: HashMap<String, String>, // Idempotency-Key to the payment it made
+}
+
+impl Sim {
+    /// An id shaped like Method's, a prefix then 15 letters and digits
+    fn new_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        // The time keeps ids from repeating across restarts, which forget the counter
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let seed = format!("{}:{}:{}", prefix, self.next_id, nanos);
+        let digest = Sha256::digest(seed.as_bytes());
+        let suffix: String = digest
+            .iter()
+            .take(15)
+            .map(|byte| ID_CHARS[*byte as usize % ID_CHARS.len()] as char)
+            .collect();
+        format!("{}_{}", prefix, suffix)
+    }
+}
+
+#[derive(Clone)]
+struct SimState {
+    sim: Arc<Mutex<Sim>>,
+    status_step: Duration,
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let state = SimState {
+        sim: Arc::new(Mutex::new(Sim::default())),
+        status_step: get_status_step(),
+    };
+    let app = Router::new()
+        .route("/entities", get(get_entities).post(post_entity))
+        .route("/accounts", get(get_accounts).post(post_account))
+        .route("/payments", get(get_payments).post(post_payment))
+        .route("/payments/:id", get(get_payment))
+        .layer(Extension(state));
+
+    let socket_addr = SocketAddr::from(([0, 0, 0, 0], get_port()));
+    info!("Simulating Method at http://{}", socket_addr);
+    if let Err(e) = axum::Server::bind(&socket_addr)
+        .serve(app.into_make_service())
+        .await
+    {
+        panic!("Failed to start simulator due to '{}'", e)
+    }
+}
+
+async fn post_entity(Extension(state): Extension<SimState>, body: Bytes) -> Response {
+    let entity: Entity = match parse_body(&body) {
+        Ok(entity) => entity,
+        Err(response) => return response,
+    };
+    if let Err(message) = validate_entity(&entity) {
+        return failure(StatusCode::BAD_REQUEST, 12001, message);
+    }
+
+    let mut sim = state.sim.lock().unwrap();
+    let now = Utc::now().to_rfc3339();
+    let response = EntityResponse {
+        id: sim.new_id("ent"),
+        entity_type: entity.entity_type,
+        individual: entity.individual,
+        corporation: None,
+        receive_only: None,
+        address: entity.address,
+        capabilities: vec![
+            String::from("payments:send"),
+            String::from("payments:receive"),
+        ],
+        error: None,
+        status: String::from("active"),
+        metadata: None,
+        updated_at: now.clone(),
+        created_at: now,
+    };
+    info!("Created entity {}", response.id);
+    let created = success(&response);
+    sim.entities.push(response);
+    created
+}
+
+async fn get_entities(
+    Extension(state): Extension<SimState>,
+    Query(params): Query<ListParams>,
+) -> Response {
+    let sim = state.sim.lock().unwrap();
+    match list(&sim.entities, |entity| &entity.created_at, &params) {
+        Ok(entities) => success(&entities),
+        Err(response) => response,
+    }
+}
+
+async fn post_account(Extension(state): Extension<SimState>, body: Bytes) -> Response {
+    let account: DestAccount = match parse_body(&body) {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+
+    let mut sim = state.sim.lock().unwrap();
+    if !sim
+        .entities
+        .iter()
+        .any(|entity| entity.id == account.holder_id)
+    {
+        // Holders created before the simulator last started are unknown, but look the part
+        if !account.holder_id.starts_with("ent_") {
+            let message = format!("Invalid holder_id {}", account.holder_id);
+            return failure(StatusCode::BAD_REQUEST, 13001, message);
+        }
+        warn!("Unknown holder {}, accepting it", account.holder_id);
+    }
+    if let Err(message) = validate_liability(&account) {
+        return failure(StatusCode::BAD_REQUEST, 13002, message);
+    }
+
+    let now = Utc::now().to_rfc3339();
+    let number = &account.liability.account_number;
+    let response = AccountResponse {
+        id: sim.new_id("acc"),
+        entity_type: None,
+        holder_id: Some(account.holder_id),
+        acc_type: String::from("liability"),
+        ach: None,
+        liability: Some(Liability {
+            mch_id: account.liability.mch_id,
+            mask: number[number.len().saturating_sub(4)..].to_string(),
+            liability_type: String::from("student_loan"),
+            data_status: String::from("active"),
+            data_last_successful_sync: None,
+            loan: None,
+        }),
+        clearing: None,
+        metadata: None,
+        status: String::from("active"),
+        capabilities: vec![String::from("payments:receive")],
+        error: None,
+        updated_at: now.clone(),
+        created_at: now,
+    };
+    info!("Created account {}", response.id);
+    let created = success(&response);
+    sim.accounts.push(response);
+    created
+}
+
+async fn get_accounts(Extension(state): Extension<SimState>) -> Response {
+    let sim = state.sim.lock().unwrap();
+    success(&sim.accounts)
+}
+
+async fn post_payment(
+    Extension(state): Extension<SimState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let payment: Payment = match parse_body(&body) {
+        Ok(payment) => payment,
+        Err(response) => return response,
+    };
+    if let Err(message) = validate_payment(&payment) {
+        return failure(StatusCode::BAD_REQUEST, 14001, message);
+    }
+
+    let mut sim = state.sim.lock().unwrap();
+    let idempotency_key = headers
+        .get("Idempotency-Key")
+        .and_then(|key| key.to_str().ok())
+        .map(str::to_string);
+    // A repeated key gets the payment it made the first time
+    if let Some(payment_id) = idempotency_key
+        .as_ref()
+        .and_then(|key| sim.idempotency_keys.get(key))
+    {
+        let payment_id = payment_id.clone();
+        if let Some((_, payment)) = sim.payments.iter().find(|(_, p)| p.id == payment_id) {
+            info!("Replaying payment {}", payment_id);
+            return success(payment);
+        }
+    }
+
+    let created_at = Utc::now();
+    let settles = (created_at + ChronoDuration::days(2))
+        .format("%Y-%m-%d")
+        .to_string();
+    let response = PaymentResponse {
+        id: sim.new_id("pmt"),
+        reversal_id: None,
+        source_trace_id: None,
+        destination_trace_id: None,
+        source: payment.source,
+        destination: payment.destination,
+        amount: payment.amount,
+        description: payment.description,
+        status: String::from(PAYMENT_STATUSES[0]),
+        error: None,
+        metadata: payment.metadata,
+        estimated_completion_date: settles.clone(),
+        source_settlement_date: settles.clone(),
+        destination_settlement_date: settles,
+        fee: None,
+        created_at: created_at.to_rfc3339(),
+        updated_at: created_at.to_rfc3339(),
+    };
+    info!("Created payment {} of {}", response.id, response.amount);
+    let created = success(&response);
+    if let Some(key) = idempotency_key {
+        sim.idempotency_keys.insert(key, response.id.clone());
+    }
+    sim.payments.push((created_at, response));
+    created
+}
+
+async fn get_payments(
+    Extension(state): Extension<SimState>,
+    Query(params): Query<ListParams>,
+) -> Response {
+    let mut sim = state.sim.lock().unwrap();
+    for (created_at, payment) in sim.payments.iter_mut() {
+        advance(payment, *created_at, state.status_step);
+    }
+    let payments: Vec<&PaymentResponse> = sim.payments.iter().map(|(_, p)| p).collect();
+    match list(&payments, |payment| &payment.created_at, &params) {
+        Ok(payments) => success(&payments),
+        Err(response) => response,
+    }
+}
+
+async fn get_payment(Extension(state): Extension<SimState>, Path(id): Path<String>) -> Response {
+    let mut sim = state.sim.lock().unwrap();
+    match sim
+        .payments
+        .iter_mut()
+        .find(|(_, payment)| payment.id == id)
+    {
+        Some((created_at, payment)) => {
+            advance(payment, *created_at, state.status_step);
+            success(&*payment)
+        }
+        None => failure(
+            StatusCode::NOT_FOUND,
+            14004,
+            format!("Payment {} not found", id),
+        ),
+    }
+}
+
+/// The requested page of `items` created within the requested dates
+fn list<'a, T>(
+    items: &'a [T],
+    created_at: impl Fn(&T) -> &str,
+    params: &ListParams,
+) -> Result<Vec<&'a T>, Response> {
+    let from_date = parse_date(&params.from_date)?;
+    let to_date = parse_date(&params.to_date)?;
+    let dates = from_date.unwrap_or(NaiveDate::MIN)..=to_date.unwrap_or(NaiveDate::MAX);
+    let page_limit = params.page_limit.unwrap_or(100).max(1);
+    let page = params.page.unwrap_or(1).max(1);
+    Ok(items
+        .iter()
+        .filter(|item| {
+            let created = match DateTime::parse_from_rfc3339(created_at(item)) {
+                Ok(created) => created.with_timezone(&Utc).date_naive(),
+                Err(_) => return false,
+            };
+            dates.contains(&created)
+        })
+        .skip((page - 1) * page_limit)
+        .take(page_limit)
+        .collect())
+}
+
+fn parse_date(date: &Option<String>) -> Result<Option<NaiveDate>, Response> {
+    match date {
+        None => Ok(None),
+        Some(date) => match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(date) => Ok(Some(date)),
+            Err(_) => Err(failure(
+                StatusCode::BAD_REQUEST,
+                10001,
+                format!("Invalid date {}, expected YYYY-MM-DD", date),
+            )),
+        },
+    }
+}
+
+/// Moves the payment on to the status it has reached by now
+fn advance(payment: &mut PaymentResponse, created_at: DateTime<Utc>, step: Duration) {
+    let age = (Utc::now() - created_at).to_std().unwrap_or_default();
+    let steps = (age.as_secs() / step.as_secs().max(1)) as usize;
+    let status = PAYMENT_STATUSES[steps.min(PAYMENT_STATUSES.len() - 1)];
+    if payment.status != status {
+        payment.status = String::from(status);
+        payment.updated_at = Utc::now().to_rfc3339();
+    }
+}
+
+fn validate_entity(entity: &Entity) -> Result<(), String> {
+    if entity.entity_type != "individual" {
+        return Err(format!("Unsupported entity type {}", entity.entity_type));
+    }
+    let individual = &entity.individual;
+    for (field, value) in [
+        ("individual.first_name", &individual.first_name),
+        ("individual.last_name", &individual.last_name),
+        ("individual.phone", &individual.phone),
+        ("address.line1", &entity.address.line1),
+        ("address.city", &entity.address.city),
+    ] {
+        if value.trim().is_empty() {
+            return Err(format!("{} is required", field));
+        }
+    }
+    if !individual.email.contains('@') {
+        return Err(format!("Invalid individual.email {}", individual.email));
+    }
+    if DateTime::parse_from_rfc3339(&format!("{}T00:00:00Z", individual.dob)).is_err() {
+        return Err(format!("Invalid individual.dob {}", individual.dob));
+    }
+    let address = &entity.address;
+    if address.state.len() != 2 || !address.state.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(format!("Invalid address.state {}", address.state));
+    }
+    if address.zip.len() != 5 || !address.zip.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("Invalid address.zip {}", address.zip));
+    }
+    Ok(())
+}
+
+fn validate_liability(account: &DestAccount) -> Result<(), String> {
+    let liability = &account.liability;
+    if !liability.mch_id.starts_with("mch_") {
+        return Err(format!("Invalid liability.mch_id {}", liability.mch_id));
+    }
+    let number = &liability.account_number;
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("Invalid liability.account_number {}", number));
+    }
+    Ok(())
+}
+
+fn validate_payment(payment: &Payment) -> Result<(), String> {
+    if payment.amount.cents() == 0 {
+        return Err(String::from("amount must be greater than 0"));
+    }
+    for (field, id) in [
+        ("source", &payment.source),
+        ("destination", &payment.destination),
+    ] {
+        if !id.starts_with("acc_") {
+            return Err(format!("Invalid {} {}", field, id));
+        }
+    }
+    if payment.source == payment.destination {
+        return Err(String::from("source and destination must differ"));
+    }
+    Ok(())
+}
+
+/// Reads the body as `T`, answering the way Method does when it doesn't fit
+fn parse_body<T: DeserializeOwned>(body: &[u8]) -> Result<T, Response> {
+    serde_json::from_slice(body).map_err(|e| {
+        failure(
+            StatusCode::BAD_REQUEST,
+            10001,
+            format!("Invalid request: {}", e),
+        )
+    })
+}
+
+fn success<T: Serialize>(data: &T) -> Response {
+    let body = json!({ "success": true, "data": data, "message": null });
+    (StatusCode::OK, Json(body))
+}
+
+fn failure(status: StatusCode, code: u32, message: String) -> Response {
+    warn!("Rejecting request: {}", message);
+    let body = json!({
+        "success": false,
+        "data": {
+            "error": {
+                "type": "INVALID_REQUEST",
+                "sub_type": "INVALID_REQUEST",
+                "code": code,
+                "message": message,
+            }
+        },
+        "message": message,
+    });
+    (status, Json(body))
+}
+
+fn get_port() -> u16 {
+    parse_or("SIM_PORT", 3002)
+}
+
+fn get_status_step() -> Duration {
+    Duration::from_secs(positive_or("SIM_STATUS_STEP_SECS", 30))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(page: usize, page_limit: usize, from: Option<&str>, to: Option<&str>) -> ListParams {
+        ListParams {
+            page: Some(page),
+            page_limit: Some(page_limit),
+            from_date: from.map(str::to_string),
+            to_date: to.map(str::to_string),
+        }
+    }
+
+    fn created(dates: &[&str]) -> Vec<String> {
+        dates
+            .iter()
+            .map(|date| format!("{}T12:00:00+00:00", date))
+            .collect()
+    }
+
+    fn listed(items: &[String], params: &ListParams) -> Vec<String> {
+        list(items, String::as_str, params)
+            .unwrap_or_else(|_| panic!("{:?} was rejected", params))
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    fn payment(status: &str) -> PaymentResponse {
+        serde_json::from_value(json!({
+            "id": "pmt_1",
+            "source": "acc_1",
+            "destination": "acc_2",
+            "amount": 100,
+            "description": "1",
+            "status": status,
+            "estimated_completion_date": "2024-01-03",
+            "source_settlement_date": "2024-01-03",
+            "destination_settlement_date": "2024-01-03",
+            "created_at": "2024-01-01T00:00:00+00:00",
+            "updated_at": "2024-01-01T00:00:00+00:00",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn lists_a_page_at_a_time() {
+        let items = created(&["2024-01-01", "2024-01-02", "2024-01-03", "2024-01-04"]);
+        assert_eq!(listed(&items, &params(1, 3, None, None)), items[..3]);
+        assert_eq!(listed(&items, &params(2, 3, None, None)), items[3..]);
+        assert!(listed(&items, &params(3, 3, None, None)).is_empty());
+        // Out of range paging is clamped rather than rejected, as Method does
+        assert_eq!(listed(&items, &params(0, 0, None, None)), items[..1]);
+    }
+
+    #[test]
+    fn lists_only_the_requested_dates() {
+        let items = created(&["2024-01-01", "2024-01-02", "2024-01-03", "2024-01-04"]);
+        assert_eq!(
+            listed(
+                &items,
+                &params(1, 100, Some("2024-01-02"), Some("2024-01-03"))
+            ),
+            items[1..3]
+        );
+        assert_eq!(
+            listed(&items, &params(1, 100, Some("2024-01-04"), None)),
+            items[3..]
+        );
+        assert_eq!(
+            listed(&items, &params(1, 100, None, Some("2024-01-01"))),
+            items[..1]
+        );
+        // Pages are taken after filtering
+        assert_eq!(
+            listed(&items, &params(2, 1, Some("2024-01-02"), None)),
+            items[2..3]
+        );
+    }
+
+    #[test]
+    fn rejects_dates_not_in_methods_format() {
+        let items = created(&["2024-01-01"]);
+        let rejected = list(
+            &items,
+            String::as_str,
+            &params(1, 100, Some("01/01/2024"), None),
+        );
+        assert!(matches!(rejected, Err((StatusCode::BAD_REQUEST, _))));
+    }
+
+    #[test]
+    fn advances_payments_a_status_per_step() {
+        let step = Duration::from_secs(30);
+        for (age, status) in [
+            (0, "pending"),
+            (29, "pending"),
+            (30, "processing"),
+            (59, "processing"),
+            (60, "sent"),
+            (600, "sent"),
+        ] {
+            let mut payment = payment("pending");
+            advance(
+                &mut payment,
+                Utc::now() - ChronoDuration::seconds(age),
+                step,
+            );
+            assert_eq!(payment.status, status, "after {}s", age);
+        }
+    }
+
+    #[test]
+    fn leaves_a_payment_alone_until_it_moves_on() {
+        let mut payment = payment("pending");
+        advance(&mut payment, Utc::now(), Duration::from_secs(30));
+        assert_eq!(payment.updated_at, "2024-01-01T00:00:00+00:00");
+
+        advance(
+            &mut payment,
+            Utc::now() - ChronoDuration::seconds(30),
+            Duration::from_secs(30),
+        );
+        assert_ne!(payment.updated_at, "2024-01-01T00:00:00+00:00");
+    }
+}
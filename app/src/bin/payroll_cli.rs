@@ -0,0 +1,294 @@
+use method_assesment::schema::db::create_from_env;
+use method_assesment::schema::money::Money;
+use method_assesment::schema::row_error::RowError;
+use method_assesment::schema::xml_parse::{XmlParse, XmlStatus};
+use method_assesment::utility::field_mapping::FieldMapping;
+use method_assesment::utility::imports::{start_import, ImportRequest, Started};
+use method_assesment::utility::jobs;
+use method_assesment::utility::method_client::MethodClient;
+use method_assesment::utility::parser::{validate_offline, FileFormat};
+use method_assesment::utility::upload;
+use method_assesment::utility::xsd::PAYROLL_SCHEMA;
+use method_assesment::State;
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+const USAGE: &str = "\
+Usage: payroll-cli <command> <file> [options]
+
+Commands:
+  validate   Check every row of the file, without any network access
+  summarize  Total the valid rows by payor and by branch
+  import     Pay the file through Method, using DB_* and METHOD_* from the environment
+
+Options:
+  --format <xml|csv|json|ndjson>  Defaults to the file's extension
+  --profile <name>                Field mapping profile the XML was exported with
+  --strict                        Check XML against the published schema first
+  --override-reason <reason>      Import a file identical to an earlier import anyway";
+
+enum Command {
+    Validate,
+    Summarize,
+    Import,
+}
+
+struct Options {
+    command: Command,
+    file: PathBuf,
+    format: Option<FileFormat>,
+    profile: Option<String>,
+    strict: bool,
+    override_reason: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let command = match args.next().as_deref() {
+        Some("validate") => Command::Validate,
+        Some("summarize") => Command::Summarize,
+        Some("import") => Command::Import,
+        Some(other) => return Err(format!("Unknown command '{}'", other)),
+        None => return Err(String::from("No command given")),
+    };
+
+    let mut file: Option<PathBuf> = None;
+    let mut format: Option<FileFormat> = None;
+    let mut profile: Option<String> = None;
+    let mut strict = false;
+    let mut override_reason: Option<String> = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let name = args.next();
+                match name.as_deref().and_then(FileFormat::from_name) {
+                    Some(parsed) => format = Some(parsed),
+                    None => return Err(format!("Unknown format {:?}", name)),
+                }
+            }
+            "--profile" => profile = Some(args.next().ok_or("--profile needs a name")?),
+            "--strict" => strict = true,
+            "--override-reason" => {
+                override_reason = Some(args.next().ok_or("--override-reason needs a reason")?)
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+            _ if file.is_none() => file = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+    }
+
+    if strict && profile.is_some() {
+        return Err(String::from(
+            "--strict checks the default layout and can't be used with --profile",
+        ));
+    }
+
+    Ok(Options {
+        command,
+        file: file.ok_or("No file given")?,
+        format,
+        profile,
+        strict,
+        override_reason,
+    })
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    env_logger::init();
+
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let mapping = match FieldMapping::load(options.profile.as_deref()) {
+        Ok(mapping) => mapping,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+    let filename = options.file.display().to_string();
+    let format = options
+        .format
+        .unwrap_or_else(|| FileFormat::detect(None, &filename));
+
+    match options.command {
+        Command::Validate | Command::Summarize => {
+            if options.strict && format == FileFormat::Xml {
+                let violations = match open(&options) {
+                    Ok(file) => PAYROLL_SCHEMA.check(file),
+                    Err(code) => return code,
+                };
+                if !violations.is_empty() {
+                    let errors: Vec<RowError> = violations
+                        .iter()
+                        .map(|violation| violation.to_row_error(None))
+                        .collect();
+                    print_errors(&errors);
+                    println!("{} failed schema validation", filename);
+                    return ExitCode::FAILURE;
+                }
+            }
+
+            let report = match open(&options) {
+                Ok(file) => validate_offline(file, format, &mapping),
+                Err(code) => return code,
+            };
+
+            if let Command::Summarize = options.command {
+                print_totals("Payor", &report.payment_map_acc);
+                print_totals("Branch", &report.payment_map_branch);
+            } else {
+                print_errors(&report.errors);
+            }
+            println!(
+                "{} valid rows, {} rows with errors",
+                report.rows.len(),
+                report.errors.len()
+            );
+
+            if report.errors.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Command::Import => import(&options, format, filename).await,
+    }
+}
+
+/// Imports the file the way an upload is imported, then runs its job here rather than waiting
+/// for a worker
+async fn import(options: &Options, format: FileFormat, filename: String) -> ExitCode {
+    let db_client = match create_from_env().await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Failed to connect to the DB due to {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let pool = db_client.pool().expect("Pool was initialized").clone();
+    let state = State::new(pool.clone(), Arc::new(MethodClient::from_env()));
+
+    let (file, spooled) = match upload::spool_local(&options.file).await {
+        Ok(spooled) => spooled,
+        Err(e) => {
+            eprintln!("Failed to copy {} for import due to {}", filename, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let request = ImportRequest {
+        filename: filename.clone(),
+        format,
+        profile: options.profile.clone(),
+        strict: options.strict,
+        override_reason: options.override_reason.clone(),
+        // A worker on another host couldn't read the file spooled here
+        run_here: true,
+    };
+    let (xml, job) = match start_import(&pool, file, spooled, &request).await {
+        Started::Queued(xml, job) => (*xml, job),
+        Started::Duplicate(earlier) => {
+            eprintln!(
+                "{} is identical to xml {} ({}) imported at {}, pass --override-reason to import it again",
+                filename,
+                earlier.id.unwrap_or_default(),
+                earlier.filename,
+                earlier.started_at
+            );
+            return ExitCode::FAILURE;
+        }
+        Started::Rejected {
+            xml_id,
+            reason,
+            errors,
+        } => {
+            print_errors(&errors);
+            match xml_id {
+                Some(id) => eprintln!("Failed to import xml {} due to {}", id, reason),
+                None => eprintln!("Failed to import {} due to {}", filename, reason),
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+    let id = xml.id.expect("Id was set");
+
+    if !jobs::run_now(&state, job).await {
+        eprintln!(
+            "Lost the job for xml {} to a job worker part way, see GET /jobs",
+            id
+        );
+        return ExitCode::FAILURE;
+    }
+
+    let errors = match RowError::get_all_by_xml_id(&pool, id).await {
+        Ok(errors) => errors,
+        Err(e) => {
+            eprintln!("Failed to get row errors due to {}", e);
+            vec![]
+        }
+    };
+    print_errors(&errors);
+
+    let status = match XmlParse::get_status(&pool, id).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("Failed to get the outcome of xml {} due to {}", id, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let paid = XmlParse::count_transactions(&pool, id)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to count payments of xml {} due to {}", id, e);
+            0
+        });
+    println!(
+        "xml {} is {}, {} payments made, {} rows with errors",
+        id,
+        status,
+        paid,
+        errors.len()
+    );
+    if status == XmlStatus::Queued {
+        println!("A job worker will retry it once the API is running");
+    }
+
+    if status == XmlStatus::Finished {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn open(options: &Options) -> Result<File, ExitCode> {
+    File::open(&options.file).map_err(|e| {
+        eprintln!("Failed to open {} due to {}", options.file.display(), e);
+        ExitCode::FAILURE
+    })
+}
+
+fn print_errors(errors: &[RowError]) {
+    for error in errors {
+        println!(
+            "row {} (line {}, column {}) {}: {}",
+            error.row_index, error.line, error.column_number, error.element, error.reason
+        );
+    }
+}
+
+fn print_totals(title: &str, totals: &HashMap<String, Money>) {
+    let mut totals: Vec<(&String, &Money)> = totals.iter().collect();
+    totals.sort();
+    println!("{} totals:", title);
+    for (key, amount) in totals {
+        println!("  {}\t{}", key, amount);
+    }
+}
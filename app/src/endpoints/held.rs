@@ -0,0 +1,119 @@
+use crate::entities::Persist;
+use crate::schema::held_payment::HeldPayment;
+use crate::schema::transaction_row::{RowResult, TransactionRow};
+use crate::schema::{SqlString, CRUD};
+use crate::State;
+use axum::extract::Path;
+use axum::{Extension, Json};
+use hyper::StatusCode;
+use log::{error, info};
+use std::collections::HashMap;
+
+/// Keeps the row's result in step with the held payment, the payment itself already stands
+async fn record_row_result(held: &HeldPayment, state: &State, result: RowResult) {
+    if let Err(e) =
+        TransactionRow::set_result(&state.pool, held.xml_id, held.row_index, &result).await
+    {
+        error!(
+            "Failed to record {} for row {} of xml {} due to {}",
+            result.status, held.row_index, held.xml_id, e
+        );
+    }
+}
+
+async fn get_held(state: &State, id: u64) -> Result<HeldPayment, StatusCode> {
+    HeldPayment::get_by(&state.pool, HashMap::from([("Id", SqlString::from(id))]))
+        .await
+        .map_err(|e| {
+            error!("Failed to get held payment due to {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            error!("Held payment with id {} not found", id);
+            StatusCode::NOT_FOUND
+        })
+}
+
+/// Pays a held payment after someone has confirmed it is not a duplicate
+pub async fn release_handler(
+    Extension(state): Extension<State>,
+    Path(id): Path<u64>,
+) -> Result<Json<HeldPayment>, StatusCode> {
+    let mut held = get_held(&state, id).await?;
+
+    // Claimed before paying so a second release of the same payment can't pay it again
+    let claimed = held
+        .set_status(&state.pool, HeldPayment::HELD, HeldPayment::RELEASED, None)
+        .await
+        .map_err(|e| {
+            error!("Failed to release held payment {} due to {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !claimed {
+        error!("Held payment {} is already {}", id, held.status);
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let mut transaction = held.to_transaction();
+    // The error isn't Send, so it can't be held across the await below
+    let paid = transaction
+        .create(&state.pool, state.method.as_ref(), ())
+        .await
+        .map_err(|e| e.to_string());
+    if let Err(e) = paid {
+        error!("Failed to pay held payment {} due to {}", id, e);
+        if let Err(e) = held
+            .set_status(&state.pool, HeldPayment::RELEASED, HeldPayment::HELD, None)
+            .await
+        {
+            error!("Failed to hold payment {} again due to {}", id, e);
+        }
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+
+    held.set_status(
+        &state.pool,
+        HeldPayment::RELEASED,
+        HeldPayment::RELEASED,
+        transaction.method_id.clone(),
+    )
+    .await
+    .map_err(|e| {
+        error!(
+            "Failed to record payment of held payment {} due to {}",
+            id, e
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    info!("Released held payment {}", id);
+    record_row_result(&held, &state, RowResult::paid(&transaction, None)).await;
+
+    Ok(Json(held))
+}
+
+/// Drops a held payment that was a duplicate, it is kept for the report but never paid
+pub async fn dismiss_handler(
+    Extension(state): Extension<State>,
+    Path(id): Path<u64>,
+) -> Result<Json<HeldPayment>, StatusCode> {
+    let mut held = get_held(&state, id).await?;
+
+    let dismissed = held
+        .set_status(&state.pool, HeldPayment::HELD, HeldPayment::DISMISSED, None)
+        .await
+        .map_err(|e| {
+            error!("Failed to dismiss held payment {} due to {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !dismissed {
+        error!("Held payment {} is already {}", id, held.status);
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let result = RowResult::dismissed(&held.to_transaction(), held.reason.clone());
+    record_row_result(&held, &state, result).await;
+
+    Ok(Json(held))
+}
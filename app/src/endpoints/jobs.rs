@@ -0,0 +1,16 @@
+use crate::schema::job::Job;
+use crate::State;
+use axum::{Extension, Json};
+use hyper::StatusCode;
+use log::error;
+
+/// Every job, newest first, with how many attempts it took and why the last one failed
+pub async fn get_handler(Extension(state): Extension<State>) -> Result<Json<Vec<Job>>, StatusCode> {
+    match Job::get_all(&state.pool).await {
+        Ok(jobs) => Ok(Json(jobs)),
+        Err(e) => {
+            error!("Failed to get jobs due to {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
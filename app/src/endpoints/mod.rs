@@ -1,3 +1,5 @@
+pub mod held;
+pub mod jobs;
 pub mod reports;
-pub(crate) mod transactions;
+pub mod transactions;
 pub mod xmls;
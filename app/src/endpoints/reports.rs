@@ -1,25 +1,31 @@
 use crate::endpoints::transactions::TransactionQueryParams;
 use crate::entities::payment_response::PaymentResponse;
 use crate::schema::employee::Employee;
-use crate::schema::xml_parse::XmlParse;
+use crate::schema::held_payment::HeldPayment;
+use crate::schema::money::Money;
+use crate::schema::xml_parse::{XmlParse, XmlStatus};
 use crate::schema::{SqlString, CRUD};
-use crate::utility::method_client::get_payments;
+use crate::utility::method_client::{list_payments, ListQuery};
 use crate::State;
 use axum::extract::Query;
 use axum::{Extension, Json};
+use chrono::{NaiveDate, NaiveDateTime};
+use futures_util::TryStreamExt;
 use hyper::StatusCode;
-use log::{debug, error};
+use log::{debug, error, warn};
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Serialize, Deserialize)]
 pub struct ParseResponse {
     xml_id: u64,
-    processing: bool,
-    payment_map_acc: HashMap<String, u64>,
-    payment_map_branch: HashMap<String, u64>,
+    status: XmlStatus,
+    processing: bool, // Still queued or being processed
+    payment_map_acc: HashMap<String, Money>,
+    payment_map_branch: HashMap<String, Money>,
     payment_statuses: Vec<PaymentStatus>,
+    held_payments: Vec<HeldPayment>, // Likely duplicates waiting on review
 }
 
 #[derive(Serialize, Deserialize)]
@@ -29,7 +35,7 @@ pub struct PaymentStatus {
     source: String,
     estimated_completion_date: String,
     status: String,
-    amount: u64,
+    amount: Money,
     metadata: Option<HashMap<String, String>>,
 }
 
@@ -53,24 +59,15 @@ pub async fn get_handler(
 ) -> Result<Json<ParseResponse>, StatusCode> {
     let mut response = ParseResponse {
         xml_id: query.xml_id,
+        status: XmlStatus::Queued,
         processing: false,
         payment_map_acc: Default::default(),
         payment_map_branch: Default::default(),
         payment_statuses: vec![],
+        held_payments: vec![],
     };
     debug!("Generating report");
 
-    let payment_responses = get_payments(HashMap::default()).await.map_err(|e| {
-        error!("Failed to get payments due to {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    response.payment_statuses = payment_responses
-        .into_iter()
-        .filter(|pr| pr.description == query.xml_id.to_string())
-        .map(|pr| PaymentStatus::from(pr))
-        .collect();
-
     let xml = match XmlParse::get_by(
         &state.pool,
         HashMap::from([("Id", SqlString::from(query.xml_id))]),
@@ -89,10 +86,34 @@ pub async fn get_handler(
         Some(xml) => xml.clone(),
     };
 
-    response.processing = match xml.status.as_str() {
-        "Finished" => false,
-        _ => true,
+    // Every payment of the import was made after it started, so older pages aren't fetched
+    let list_query = ListQuery {
+        from_date: started_on(&xml),
+        ..Default::default()
     };
+    let payment_responses: Vec<PaymentResponse> = list_payments(state.method.as_ref(), list_query)
+        .try_collect()
+        .await
+        .map_err(|e| {
+            error!("Failed to get payments due to {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    response.payment_statuses = payment_responses
+        .into_iter()
+        .filter(|pr| pr.description == query.xml_id.to_string())
+        .map(PaymentStatus::from)
+        .collect();
+
+    response.status = xml.status;
+    response.processing = !xml.status.is_final();
+
+    response.held_payments = HeldPayment::get_all_by_xml_id(&state.pool, query.xml_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get held payments due to {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
     let transactions = XmlParse::get_all_transactions_by_xml_id(&state.pool, query.xml_id)
         .await
@@ -101,11 +122,12 @@ pub async fn get_handler(
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
-    let employee_ids: Vec<String> = transactions
-        .clone()
-        .into_iter()
-        .filter_map(|t| t.employee_id)
+    // Employees are usually paid by many rows of the same file
+    let employee_ids: HashSet<String> = transactions
+        .iter()
+        .filter_map(|t| t.employee_id.clone())
         .collect();
+    let employee_ids: Vec<String> = employee_ids.into_iter().collect();
     let employee_map: HashMap<String, Employee> =
         Employee::get_in(&state.pool, HashMap::from([("MethodId", employee_ids)]))
             .await
@@ -132,13 +154,12 @@ pub async fn get_handler(
                     .expect("Valid employee id")
                     .as_str(),
             )
-            .expect(
-                format!(
+            .unwrap_or_else(|| {
+                panic!(
                     "Employee id {} exists",
                     SqlString::from(transaction.employee_id)
                 )
-                .as_str(),
-            )
+            })
             .clone();
 
         let branch = emp.dunkin_branch.expect("Dunkin branch was set in parsing");
@@ -149,3 +170,19 @@ pub async fn get_handler(
 
     Ok(Json(response))
 }
+
+/// The day the import started, None if it can't be read
+fn started_on(xml: &XmlParse) -> Option<NaiveDate> {
+    match NaiveDateTime::parse_from_str(&xml.started_at, "%d/%m/%Y %T") {
+        Ok(started_at) => Some(started_at.date()),
+        Err(e) => {
+            warn!(
+                "Failed to read start time {} of xml {} due to {}, listing every payment",
+                xml.started_at,
+                SqlString::from(xml.id),
+                e
+            );
+            None
+        }
+    }
+}
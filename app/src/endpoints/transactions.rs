@@ -1,104 +1,318 @@
+use crate::schema::row_error::RowError;
 use crate::schema::transaction::Transaction;
 use crate::schema::xml_parse::XmlParse;
-use crate::schema::CRUD;
-use crate::utility::parser::parse;
+use crate::schema::SqlString;
+use crate::utility::field_mapping::FieldMapping;
+use crate::utility::imports::{check_schema, start_import, ImportRequest, Started};
+use crate::utility::parser::{validate, FileFormat, ValidationReport};
+use crate::utility::upload;
 use crate::State;
-use axum::extract::Query;
+use axum::extract::{BodyStream, Query};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::HeaderMap;
 use axum::{extract::Multipart, http::StatusCode, Extension, Json};
 use log::{debug, error, info};
 use serde::Deserialize;
 use serde::Serialize;
-use tokio::task;
+use tempfile::NamedTempFile;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Payload {
     base64_xml: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct UploadQueryParams {
+    /// Name of the field mapping profile the XML was exported with, the default layout if unset
+    pub profile: Option<String>,
+    /// Check XML files against the published schema before any row is processed
+    #[serde(default)]
+    pub strict: bool,
+    /// Why an identical file is being imported again, without one duplicates are rejected
+    pub override_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchQueryParams {
+    pub name: Option<String>,
+    pub override_reason: Option<String>,
+}
+
 #[derive(Debug, serde::Deserialize)]
 pub struct TransactionQueryParams {
     pub xml_id: u64,
 }
 
+#[derive(Serialize, Debug, Default)]
+pub struct UploadResponse {
+    pub accepted: Vec<XmlParse>,
+    pub rejected: Vec<RejectedFile>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RejectedFile {
+    pub filename: String,
+    pub xml_id: Option<u64>,
+    pub reason: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<RowError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<u64>,
+}
+
+impl RejectedFile {
+    fn new(filename: String, xml_id: Option<u64>, reason: String) -> Self {
+        Self {
+            filename,
+            xml_id,
+            reason,
+            errors: vec![],
+            duplicate_of: None,
+        }
+    }
+}
+
 pub async fn post_handler(
     Extension(state): Extension<State>,
+    query: Query<UploadQueryParams>,
     mut multipart: Multipart,
-) -> Result<Json<Vec<XmlParse>>, StatusCode> {
-    let mut xmls: Vec<XmlParse> = vec![];
-
-    // Todo: support multiple xml at the same time
-    // loop {
-    match multipart.next_field().await {
-        Ok(next_part) => {
-            match next_part {
-                None => {
-                    info!("Executed all fields");
-                    // break;
-                }
-                Some(field) => {
-                    let field_name = field.name().unwrap_or("No field").to_string();
-                    debug!("Parsing field: {}", field_name);
-
-                    debug!(
-                        "Creating {} entry for: {}",
-                        XmlParse::COLUMN_NAME,
-                        field_name
+) -> Result<Json<UploadResponse>, StatusCode> {
+    // Workers load the mapping again from the stored profile, it is only checked here
+    load_mapping(&query)?;
+    let mut response = UploadResponse::default();
+
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => {
+                info!("Executed all fields");
+                break;
+            }
+            Err(e) => {
+                // The rest of the body can't be read once the multipart stream is broken
+                error!("Failed to get next part due to {}", e);
+                response.rejected.push(RejectedFile::new(
+                    String::from("Unreadable field"),
+                    None,
+                    e.to_string(),
+                ));
+                break;
+            }
+        };
+
+        let field_name = field
+            .file_name()
+            .or(field.name())
+            .unwrap_or("No field")
+            .to_string();
+        let format = FileFormat::detect(field.content_type(), &field_name);
+        debug!("Parsing field: {} as {:?}", field_name, format);
+
+        let file = match upload::spool_file().await {
+            Ok(file) => file,
+            Err(e) => {
+                error!(
+                    "Failed to create spool file for {} due to {}",
+                    field_name, e
+                );
+                response
+                    .rejected
+                    .push(RejectedFile::new(field_name, None, e.to_string()));
+                continue;
+            }
+        };
+        let spooled = match upload::spool(&mut field, file.path()).await {
+            Ok(spooled) => spooled,
+            Err(e) => {
+                error!("Failed to spool field {}, due to {}", field_name, e);
+                response
+                    .rejected
+                    .push(RejectedFile::new(field_name, None, e.to_string()));
+                continue;
+            }
+        };
+
+        let request = ImportRequest {
+            filename: field_name.clone(),
+            format,
+            profile: query.profile.clone(),
+            strict: query.strict,
+            override_reason: query.override_reason.clone(),
+            run_here: false,
+        };
+        match start_import(&state.pool, file, spooled, &request).await {
+            Started::Queued(xml, _) => response.accepted.push(*xml),
+            Started::Duplicate(earlier) => {
+                let mut rejected = RejectedFile::new(
+                        field_name,
+                        None,
+                        format!(
+                            "Identical to xml {} ({}) uploaded at {}, resend with an override_reason to import it again",
+                            SqlString::from(earlier.id),
+                            earlier.filename,
+                            earlier.started_at
+                        ),
                     );
+                rejected.duplicate_of = earlier.id;
+                response.rejected.push(rejected);
+            }
+            Started::Rejected {
+                xml_id,
+                reason,
+                errors,
+            } => {
+                let mut rejected = RejectedFile::new(field_name, xml_id, reason);
+                rejected.errors = errors;
+                response.rejected.push(rejected);
+            }
+        }
+    }
+
+    Ok(Json(response))
+}
+
+fn load_mapping(query: &UploadQueryParams) -> Result<FieldMapping, StatusCode> {
+    // The published schema describes the default layout, which a profile exists to differ from
+    if query.strict && query.profile.is_some() {
+        error!("Strict mode can't be used with a field mapping profile");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    FieldMapping::load(query.profile.as_deref()).map_err(|e| {
+        error!("Failed to load field mapping due to {}", e);
+        StatusCode::BAD_REQUEST
+    })
+}
+
+/// Accepts a JSON array or NDJSON body of [crate::utility::json_parser::BatchRecord]s, for
+/// systems submitting payments directly rather than uploading a file. A batch identical to an
+/// earlier one is answered with 409 and the earlier import
+pub async fn batch_handler(
+    Extension(state): Extension<State>,
+    query: Query<BatchQueryParams>,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> Result<(StatusCode, Json<XmlParse>), StatusCode> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|c| c.to_str().ok())
+        .and_then(|c| c.split(';').next())
+        .map(|c| c.trim().to_lowercase())
+        .unwrap_or_default();
+    let format = match FileFormat::from_content_type(&content_type) {
+        Some(format @ (FileFormat::Json | FileFormat::Ndjson)) => format,
+        _ => {
+            error!("Unsupported batch content type '{}'", content_type);
+            return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+    };
+
+    let name = query.name.clone().unwrap_or_else(|| match format {
+        FileFormat::Ndjson => String::from("batch.ndjson"),
+        _ => String::from("batch.json"),
+    });
+    let file = upload::spool_file().await.map_err(|e| {
+        error!(
+            "Failed to create spool file for batch {} due to {}",
+            name, e
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let spooled = upload::spool(body, file.path()).await.map_err(|e| {
+        error!("Failed to spool batch {}, due to {}", name, e);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    let request = ImportRequest {
+        filename: name,
+        format,
+        profile: None,
+        strict: false,
+        override_reason: query.override_reason.clone(),
+        run_here: false,
+    };
+    match start_import(&state.pool, file, spooled, &request).await {
+        Started::Queued(xml, _) => Ok((StatusCode::OK, Json(*xml))),
+        Started::Duplicate(earlier) => {
+            error!("Batch is identical to xml {}", SqlString::from(earlier.id));
+            Ok((StatusCode::CONFLICT, Json(earlier)))
+        }
+        Started::Rejected { reason, .. } => {
+            error!("Failed to import batch due to {}", reason);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
 
-                    let mut xml = XmlParse::new(field_name.clone(), String::from("Init"));
-                    let id = xml.insert(&state.pool).await.map_err(|e| {
-                        error!("Failed to insert {} due to {}", XmlParse::COLUMN_NAME, e);
-                        StatusCode::INTERNAL_SERVER_ERROR
-                    })?;
-                    xml.id = Some(id);
-                    xmls.push(xml.clone());
-
-                    match field.bytes().await {
-                        Ok(bytes) => {
-                            task::spawn(async move {
-                                if let Err(_e) = parse(&state.pool, bytes, id).await {
-                                    // TODO: Fix
-                                    // error!("Failed to parse due to {}" , e);
-                                    if let Err(e) =
-                                        xml.set_finished(&state.pool, String::from("Failed")).await
-                                    {
-                                        error!(
-                                            "Failed to set xml with id {} as failed due to {}",
-                                            id, e
-                                        );
-                                    }
-                                }
-                                if let Err(e) = xml
-                                    .set_finished(&state.pool, String::from("Finished"))
-                                    .await
-                                {
-                                    error!(
-                                        "Failed to set xml with id {} as finished due to {}",
-                                        id, e
-                                    );
-                                }
-                            });
-                        }
-                        Err(e) => {
-                            error!("Failed to get bytes of field {}, due to {}", field_name, e);
-                            if let Err(e) =
-                                xml.set_finished(&state.pool, String::from("Failed")).await
-                            {
-                                error!("Failed to set xml with id {} as failed due to {}", id, e);
-                            }
-                        }
-                    }
-                }
-            };
+/// Dry run of [post_handler], reports what would be paid without contacting Method or writing
+/// to the DB
+pub async fn validate_handler(
+    Extension(state): Extension<State>,
+    query: Query<UploadQueryParams>,
+    mut multipart: Multipart,
+) -> Result<Json<ValidationReport>, StatusCode> {
+    let mapping = load_mapping(&query)?;
+    let mut field = match multipart.next_field().await {
+        Ok(Some(field)) => field,
+        Ok(None) => {
+            error!("No file was sent for validation");
+            return Err(StatusCode::BAD_REQUEST);
         }
         Err(e) => {
             error!("Failed to get next part due to {}", e);
-            // break;
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    let field_name = field
+        .file_name()
+        .or(field.name())
+        .unwrap_or("No field")
+        .to_string();
+    let format = FileFormat::detect(field.content_type(), &field_name);
+    debug!("Validating field: {} as {:?}", field_name, format);
+
+    let spooled = NamedTempFile::new().map_err(|e| {
+        error!("Failed to create temp file due to {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    upload::spool(&mut field, spooled.path())
+        .await
+        .map_err(|e| {
+            error!("Failed to spool field {}, due to {}", field_name, e);
+            StatusCode::BAD_REQUEST
+        })?;
+    let file = spooled.reopen().map_err(|e| {
+        error!("Failed to open spooled field {} due to {}", field_name, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if query.strict && format == FileFormat::Xml {
+        let violations = check_schema(spooled.path()).await.map_err(|e| {
+            error!(
+                "Failed to check {} against the schema due to {}",
+                field_name, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        if !violations.is_empty() {
+            return Ok(Json(ValidationReport {
+                errors: violations
+                    .iter()
+                    .map(|violation| violation.to_row_error(None))
+                    .collect(),
+                ..Default::default()
+            }));
         }
     }
-    // }
 
-    Ok(Json(xmls))
+    let report = validate(&state.pool, file, format, &mapping)
+        .await
+        .map_err(|e| {
+            error!("Failed to validate {} due to {}", field_name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(report))
 }
 
 pub async fn get_handler(
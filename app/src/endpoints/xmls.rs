@@ -1,10 +1,24 @@
-use crate::schema::xml_parse::XmlParse;
-use crate::schema::CRUD;
+use crate::schema::row_error::RowError;
+use crate::schema::transaction_row::TransactionRow;
+use crate::schema::xml_parse::{XmlParse, XmlStatus};
+use crate::schema::{SqlString, CRUD};
+use crate::utility::xsd::PAYROLL_XSD;
 use crate::State;
+use axum::extract::Path;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::IntoResponse;
 use axum::{Extension, Json};
+use futures_util::stream::{self, Stream, StreamExt};
 use hyper::StatusCode;
-use log::error;
+use log::{error, info};
+use serde::Serialize;
+use sqlx::{MySql, Pool};
 use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time;
 
 pub async fn get_handler(
     Extension(state): Extension<State>,
@@ -17,3 +31,298 @@ pub async fn get_handler(
         }
     }
 }
+
+async fn check_exists(state: &State, xml_id: u64) -> Result<(), StatusCode> {
+    let xmls = XmlParse::get_by(
+        &state.pool,
+        HashMap::from([("Id", SqlString::from(xml_id))]),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to get xml due to {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if xmls.is_empty() {
+        error!("XML with id {} not found", xml_id);
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(())
+}
+
+pub async fn get_errors_handler(
+    Extension(state): Extension<State>,
+    Path(xml_id): Path<u64>,
+) -> Result<Json<Vec<RowError>>, StatusCode> {
+    check_exists(&state, xml_id).await?;
+
+    let row_errors = RowError::get_all_by_xml_id(&state.pool, xml_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get row errors due to {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(row_errors))
+}
+
+/// Every row of the import with what became of it, failed rows included
+pub async fn get_rows_handler(
+    Extension(state): Extension<State>,
+    Path(xml_id): Path<u64>,
+) -> Result<Json<Vec<TransactionRow>>, StatusCode> {
+    check_exists(&state, xml_id).await?;
+
+    let rows = TransactionRow::get_all_by_xml_id(&state.pool, xml_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get transaction rows due to {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(rows))
+}
+
+#[derive(Serialize)]
+pub struct CancelResponse {
+    xml_id: u64,
+    status: XmlStatus,
+    paid: u64, // Rows paid so far, the rows already being paid may still add to it
+}
+
+/// Stops an import before any more of its rows are paid. A queued import never starts, a
+/// running one stops after the rows it has already started
+pub async fn cancel_handler(
+    Extension(state): Extension<State>,
+    Path(xml_id): Path<u64>,
+) -> Result<Json<CancelResponse>, StatusCode> {
+    let mut xml = XmlParse::get_by_id(&state.pool, xml_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get xml due to {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or_else(|| {
+            error!("XML with id {} not found", xml_id);
+            StatusCode::NOT_FOUND
+        })?;
+
+    let cancelled = xml
+        .set_status(&state.pool, XmlStatus::Cancelled)
+        .await
+        .map_err(|e| {
+            error!("Failed to cancel xml {} due to {}", xml_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !cancelled {
+        error!("XML with id {} is already {}", xml_id, xml.status);
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let paid = XmlParse::count_transactions(&state.pool, xml_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to count payments of xml {} due to {}", xml_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    info!("Cancelled xml {} after {} rows were paid", xml_id, paid);
+
+    Ok(Json(CancelResponse {
+        xml_id,
+        status: xml.status,
+        paid,
+    }))
+}
+
+/// How often a progress stream looks for changes
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How far an import has got. Every row is recorded before any is paid, so once `rows` stops
+/// growing it is the size of the file and `processed` out of `rows` is how far along it is
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImportProgress {
+    xml_id: u64,
+    status: XmlStatus,
+    rows: u64,      // Rows read from the file so far
+    processed: u64, // Rows paid, held, failed or dismissed
+    paid: u64,
+    held: u64,
+    failed: u64, // Rows that could not be parsed or paid
+}
+
+impl ImportProgress {
+    // Read from the DB rather than the parse itself, which may be running on another replica
+    async fn load(pool: &Pool<MySql>, xml_id: u64) -> Result<Self, sqlx::Error> {
+        let status = XmlParse::get_status(pool, xml_id).await?;
+        let counts = TransactionRow::count_by_status(pool, xml_id).await?;
+        let count = |status: &str| counts.get(status).copied().unwrap_or_default();
+
+        let paid = count(TransactionRow::PAID);
+        let held = count(TransactionRow::HELD);
+        let failed = count(TransactionRow::INVALID) + count(TransactionRow::FAILED);
+        Ok(Self {
+            xml_id,
+            status,
+            rows: counts.values().sum(),
+            processed: paid + held + failed + count(TransactionRow::DISMISSED),
+            paid,
+            held,
+            failed,
+        })
+    }
+}
+
+/// Streams the import's progress as server-sent `progress` events, one each time it changes,
+/// ending once the import is over
+pub async fn get_progress_handler(
+    Extension(state): Extension<State>,
+    Path(xml_id): Path<u64>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    check_exists(&state, xml_id).await?;
+
+    let load = move || {
+        let pool = state.pool.clone();
+        async move {
+            ImportProgress::load(&pool, xml_id).await.map_err(|e| {
+                error!("Failed to get progress of xml {} due to {}", xml_id, e);
+                e
+            })
+        }
+    };
+    let events = progress_changes(load, PROGRESS_INTERVAL).map(|progress| {
+        Ok(Event::default()
+            .event("progress")
+            .json_data(&progress)
+            .unwrap_or_else(|e| Event::default().event("error").data(e.to_string())))
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Each progress `load` gives that differs from the last, looking again every `interval`. Ends
+/// after the first final status, or when progress can't be loaded
+fn progress_changes<F, Fut>(load: F, interval: Duration) -> impl Stream<Item = ImportProgress>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<ImportProgress, sqlx::Error>>,
+{
+    let last: Option<ImportProgress> = None;
+    stream::unfold((load, last), move |(mut load, last)| async move {
+        if last.as_ref().is_some_and(|last| last.status.is_final()) {
+            return None;
+        }
+        loop {
+            if last.is_some() {
+                time::sleep(interval).await;
+            }
+            let progress = load().await.ok()?;
+            if last.as_ref() == Some(&progress) {
+                continue;
+            }
+            return Some((progress.clone(), (load, Some(progress))));
+        }
+    })
+}
+
+/// The schema uploads are checked against in strict mode
+pub async fn get_schema_handler() -> impl IntoResponse {
+    ([(CONTENT_TYPE, "application/xml")], PAYROLL_XSD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    fn progress(status: XmlStatus, processed: u64) -> ImportProgress {
+        ImportProgress {
+            xml_id: 1,
+            status,
+            rows: 3,
+            processed,
+            paid: processed,
+            held: 0,
+            failed: 0,
+        }
+    }
+
+    /// Loads each of `loads` in turn, counting how many were loaded
+    fn scripted(
+        loads: Vec<Result<ImportProgress, sqlx::Error>>,
+    ) -> (
+        impl FnMut() -> future::Ready<Result<ImportProgress, sqlx::Error>>,
+        Arc<Mutex<usize>>,
+    ) {
+        let mut loads = VecDeque::from(loads);
+        let count = Arc::new(Mutex::new(0));
+        let counted = count.clone();
+        let load = move || {
+            *counted.lock().unwrap() += 1;
+            future::ready(
+                loads
+                    .pop_front()
+                    .expect("Loaded after the stream should have ended"),
+            )
+        };
+        (load, count)
+    }
+
+    async fn changes(
+        loads: Vec<Result<ImportProgress, sqlx::Error>>,
+    ) -> (Vec<ImportProgress>, usize) {
+        let (load, count) = scripted(loads);
+        let changes = progress_changes(load, Duration::ZERO).collect().await;
+        let count = *count.lock().unwrap();
+        (changes, count)
+    }
+
+    #[tokio::test]
+    async fn streams_changes_until_a_final_status() {
+        let (changes, loads) = changes(vec![
+            Ok(progress(XmlStatus::Queued, 0)),
+            Ok(progress(XmlStatus::Processing, 1)),
+            Ok(progress(XmlStatus::Processing, 1)),
+            Ok(progress(XmlStatus::Processing, 2)),
+            Ok(progress(XmlStatus::Finished, 3)),
+        ])
+        .await;
+        assert_eq!(
+            changes,
+            vec![
+                progress(XmlStatus::Queued, 0),
+                progress(XmlStatus::Processing, 1),
+                progress(XmlStatus::Processing, 2),
+                progress(XmlStatus::Finished, 3),
+            ]
+        );
+        // Nothing is loaded once the import is over
+        assert_eq!(loads, 5);
+    }
+
+    #[tokio::test]
+    async fn imports_already_over_send_one_event() {
+        for status in [
+            XmlStatus::Finished,
+            XmlStatus::PartiallyFailed,
+            XmlStatus::Failed,
+            XmlStatus::Cancelled,
+        ] {
+            let (changes, loads) = changes(vec![Ok(progress(status, 3))]).await;
+            assert_eq!(changes, vec![progress(status, 3)]);
+            assert_eq!(loads, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn ends_when_progress_cannot_be_loaded() {
+        let (changes, loads) = changes(vec![
+            Ok(progress(XmlStatus::Processing, 1)),
+            Err(sqlx::Error::RowNotFound),
+        ])
+        .await;
+        assert_eq!(changes, vec![progress(XmlStatus::Processing, 1)]);
+        assert_eq!(loads, 2);
+    }
+}
@@ -4,11 +4,12 @@ use crate::entities::payment::Payment;
 use crate::entities::Error::{DatabaseError, HTTPError};
 use crate::schema::employee::Employee;
 use crate::schema::payee::Payee;
+use crate::schema::payment_attempt::PaymentAttempt;
 use crate::schema::payor::Payor;
 use crate::schema::transaction::Transaction;
 use crate::schema::{address, SqlString, CRUD};
 use crate::utility::method_client;
-use crate::utility::method_client::{post_dest_account, post_entity, post_payment};
+use crate::utility::method_client::MethodApi;
 use async_trait::async_trait;
 use log::{info, warn};
 use sqlx::{MySql, Pool};
@@ -33,6 +34,16 @@ pub enum Error {
     InvalidDataError(String),
 }
 
+impl Error {
+    /// The message Method gave when it rejected the request, if that is what failed
+    pub fn method_message(&self) -> Option<&str> {
+        match self {
+            HTTPError(method_client::Error::HTTPError(_, message)) => Some(message.as_str()),
+            _ => None,
+        }
+    }
+}
+
 impl From<sqlx::Error> for Error {
     fn from(value: sqlx::Error) -> Self {
         DatabaseError(value)
@@ -49,9 +60,20 @@ impl From<method_client::Error> for Error {
 pub trait Persist {
     type Dependencies;
 
+    /// Reuses a matching entry if one exists, otherwise creates it
     async fn persist(
         &mut self,
         pool: &Pool<MySql>,
+        method: &dyn MethodApi,
+        dependency: Self::Dependencies,
+    ) -> Result<(), Error>;
+
+    /// Creates the entry without looking for an existing one first, for callers that have
+    /// already checked
+    async fn create(
+        &mut self,
+        pool: &Pool<MySql>,
+        method: &dyn MethodApi,
         dependency: Self::Dependencies,
     ) -> Result<(), Error>;
 }
@@ -60,24 +82,25 @@ pub trait Persist {
 impl Persist for Employee {
     type Dependencies = ();
 
-    async fn persist(&mut self, pool: &Pool<MySql>, _: Self::Dependencies) -> Result<(), Error> {
+    async fn persist(
+        &mut self,
+        pool: &Pool<MySql>,
+        method: &dyn MethodApi,
+        _: Self::Dependencies,
+    ) -> Result<(), Error> {
         info!(
             "Persisting Employee {}",
             self.dunkin_id.clone().expect("DunkinId was set")
         );
         let employees = Employee::get_by(
-            &pool,
+            pool,
             HashMap::from([("DunkinId", SqlString::from(self.dunkin_id.clone()))]),
         )
         .await?;
 
         match employees.len() {
             0 => {
-                let entity = Entity::from(self.clone());
-                let entity_response = post_entity(entity).await?;
-                self.method_id = Some(entity_response.id);
-
-                self.insert(pool).await?;
+                self.create(pool, method, ()).await?;
             }
 
             1 => {
@@ -103,13 +126,32 @@ impl Persist for Employee {
         }
         Ok(())
     }
+
+    async fn create(
+        &mut self,
+        pool: &Pool<MySql>,
+        method: &dyn MethodApi,
+        _: Self::Dependencies,
+    ) -> Result<(), Error> {
+        let entity = Entity::from(self.clone());
+        let entity_response = method.post_entity(entity).await?;
+        self.method_id = Some(entity_response.id);
+
+        self.insert(pool).await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl Persist for Payor {
     type Dependencies = ();
 
-    async fn persist(&mut self, pool: &Pool<MySql>, _: Self::Dependencies) -> Result<(), Error> {
+    async fn persist(
+        &mut self,
+        pool: &Pool<MySql>,
+        method: &dyn MethodApi,
+        _: Self::Dependencies,
+    ) -> Result<(), Error> {
         // // Todo: store in Db to begin with
         // let ceo_entity = Entity {
         //     entity_type: "individual".to_string(),
@@ -140,17 +182,7 @@ impl Persist for Payor {
 
         match payors.len() {
             0 => {
-                // let mut account = SourceAccount::from(self.clone());
-                // account.holder_id = payor_owner.clone();
-                // let account_response = post_source_account(account).await?;
-                // self.method_id = Some(account_response.id);
-                //
-                // self.insert(pool).await?;
-
-                return Err(Error::InvalidDataError(format!(
-                    "Payor {} is not on of the 5(4) valid payors",
-                    SqlString::from(self.dunkin_id.clone())
-                )));
+                self.create(pool, method, ()).await?;
             }
 
             1 => {
@@ -176,6 +208,25 @@ impl Persist for Payor {
         }
         Ok(())
     }
+
+    async fn create(
+        &mut self,
+        _pool: &Pool<MySql>,
+        _method: &dyn MethodApi,
+        _: Self::Dependencies,
+    ) -> Result<(), Error> {
+        // let mut account = SourceAccount::from(self.clone());
+        // account.holder_id = payor_owner.clone();
+        // let account_response = post_source_account(account).await?;
+        // self.method_id = Some(account_response.id);
+        //
+        // self.insert(pool).await?;
+
+        Err(Error::InvalidDataError(format!(
+            "Payor {} is not on of the 5(4) valid payors",
+            SqlString::from(self.dunkin_id.clone())
+        )))
+    }
 }
 
 #[async_trait]
@@ -185,6 +236,7 @@ impl Persist for Payee {
     async fn persist(
         &mut self,
         pool: &Pool<MySql>,
+        method: &dyn MethodApi,
         dependency: Self::Dependencies,
     ) -> Result<(), Error> {
         let payees = Payee::get_by(
@@ -195,14 +247,7 @@ impl Persist for Payee {
 
         match payees.len() {
             0 => {
-                let employee_method_id = dependency;
-                let mut account = DestAccount::from(self.clone());
-                // TODO Pass in employee
-                account.holder_id = employee_method_id;
-                let account_response = post_dest_account(account).await?;
-                self.method_id = Some(account_response.id);
-
-                self.insert(pool).await?;
+                self.create(pool, method, dependency).await?;
             }
 
             1 => {
@@ -229,26 +274,82 @@ impl Persist for Payee {
 
         Ok(())
     }
+
+    async fn create(
+        &mut self,
+        pool: &Pool<MySql>,
+        method: &dyn MethodApi,
+        dependency: Self::Dependencies,
+    ) -> Result<(), Error> {
+        let employee_method_id = dependency;
+        let mut account = DestAccount::from(self.clone());
+        // TODO Pass in employee
+        account.holder_id = employee_method_id;
+        let account_response = method.post_dest_account(account).await?;
+        self.method_id = Some(account_response.id);
+
+        self.insert(pool).await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl Persist for Transaction {
     type Dependencies = ();
 
-    async fn persist(&mut self, pool: &Pool<MySql>, _: Self::Dependencies) -> Result<(), Error> {
+    async fn persist(
+        &mut self,
+        pool: &Pool<MySql>,
+        method: &dyn MethodApi,
+        _: Self::Dependencies,
+    ) -> Result<(), Error> {
         // We dont do the same checks here sine its theoretically possible for use to have a
-        // transaction for the same amount, payee, payor, xml_id, and employee id to occur
-
-        let payment = Payment {
-            amount: self.amount.clone().expect("Amount was set"),
-            source: self.payor_id.clone().expect("Payor id was set"),
-            destination: self.payee_id.clone().expect("Payee id was set"),
-            // Todo add desc
-            description: self.xml_id.expect("Xml Id was set").to_string(),
+        // transaction for the same amount, payee, payor, xml_id, and employee id to occur.
+        // Likely duplicates are held by utility::duplicates before they get here instead
+        self.create(pool, method, ()).await
+    }
+
+    async fn create(
+        &mut self,
+        pool: &Pool<MySql>,
+        method: &dyn MethodApi,
+        _: Self::Dependencies,
+    ) -> Result<(), Error> {
+        // The key is stored before the payment is sent. If the response was lost, a later attempt
+        // sends it again with the same Idempotency-Key and Method returns the payment it made
+        // instead of making another
+        let key = self.idempotency_key();
+        let payment_id = match PaymentAttempt::get_by_key(pool, &key).await? {
+            Some(attempt) => attempt.payment_id,
+            None => {
+                PaymentAttempt::new(key.clone(), self).insert(pool).await?;
+                None
+            }
+        };
+
+        let payment_id = match payment_id {
+            Some(payment_id) => {
+                info!("Recovered payment {} made for {}", payment_id, key);
+                payment_id
+            }
+            None => {
+                let payment = Payment {
+                    amount: self.amount.expect("Amount was set"),
+                    source: self.payor_id.clone().expect("Payor id was set"),
+                    destination: self.payee_id.clone().expect("Payee id was set"),
+                    // Todo add desc
+                    description: self.xml_id.expect("Xml Id was set").to_string(),
+                    metadata: Some(HashMap::from([(
+                        Payment::IDEMPOTENCY_KEY.to_string(),
+                        key.clone(),
+                    )])),
+                };
+                method.post_payment(payment, Some(&key)).await?.id
+            }
         };
-        let payment_response = post_payment(payment).await?;
+        PaymentAttempt::set_paid(pool, &key, &payment_id).await?;
 
-        self.method_id = Some(payment_response.id.clone());
+        self.method_id = Some(payment_id);
 
         self.insert(pool).await?;
         Ok(())
@@ -262,6 +363,7 @@ impl Persist for address::Address {
     async fn persist(
         &mut self,
         pool: &Pool<MySql>,
+        method: &dyn MethodApi,
         _dependency: Self::Dependencies,
     ) -> Result<(), Error> {
         let addresses = address::Address::get_by(
@@ -270,14 +372,14 @@ impl Persist for address::Address {
                 ("Line1", SqlString::from(self.line1.clone())),
                 ("City", SqlString::from(self.city.clone())),
                 ("StateName", SqlString::from(self.state.clone())),
-                ("Zip", SqlString::from(self.zip.clone())),
+                ("Zip", SqlString::from(self.zip)),
             ]),
         )
         .await?;
 
         match addresses.len() {
             0 => {
-                self.id = Some(self.insert(pool).await?);
+                self.create(pool, method, ()).await?;
             }
 
             1 => {
@@ -298,4 +400,14 @@ impl Persist for address::Address {
 
         Ok(())
     }
+
+    async fn create(
+        &mut self,
+        pool: &Pool<MySql>,
+        _method: &dyn MethodApi,
+        _dependency: Self::Dependencies,
+    ) -> Result<(), Error> {
+        self.id = Some(self.insert(pool).await?);
+        Ok(())
+    }
 }
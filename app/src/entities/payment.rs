@@ -1,9 +1,18 @@
-use serde::Serialize;
+use crate::schema::money::Money;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Payment {
-    pub amount: u64,
+    pub amount: Money,
     pub source: String,
     pub destination: String,
     pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+impl Payment {
+    /// Metadata entry holding the payment's idempotency key, so it can be found again later
+    pub const IDEMPOTENCY_KEY: &'static str = "idempotency_key";
 }
@@ -1,3 +1,4 @@
+use crate::schema::money::Money;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -9,7 +10,7 @@ pub struct PaymentResponse {
     pub destination_trace_id: Option<String>,
     pub source: String,
     pub destination: String,
-    pub amount: u64,
+    pub amount: Money,
     pub description: String,
     pub status: String,
     pub error: Option<String>,
@@ -4,25 +4,35 @@ pub mod schema;
 pub mod utility;
 
 use crate::schema::db::create_from_env;
+use crate::utility::env::positive_or;
+use crate::utility::method_client::{MethodApi, MethodClient};
 use axum::extract::DefaultBodyLimit;
 use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, ORIGIN};
 use axum::http::Method;
 use axum::{routing::get, routing::post, Extension, Router};
-use log::{info, warn};
+use log::info;
 use sqlx::{MySql, Pool};
-use std::env;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
 
 #[derive(Clone)]
 pub struct State {
     pool: Pool<MySql>,
+    method: Arc<dyn MethodApi>,
+}
+
+impl State {
+    pub fn new(pool: Pool<MySql>, method: Arc<dyn MethodApi>) -> Self {
+        Self { pool, method }
+    }
 }
 
 pub async fn serve() {
     let max_upload = get_max_upload_size();
 
     let state = setup_state().await;
+    utility::jobs::spawn_workers(state.clone());
 
     // Configure the CORS layer
     let cors = CorsLayer::new()
@@ -37,8 +47,27 @@ pub async fn serve() {
         .route("/", get(|| async { "Hello, World!" }))
         .route("/transactions", post(endpoints::transactions::post_handler))
         .route("/transactions", get(endpoints::transactions::get_handler))
+        .route(
+            "/transactions/batch",
+            post(endpoints::transactions::batch_handler),
+        )
+        .route(
+            "/transactions/validate",
+            post(endpoints::transactions::validate_handler),
+        )
         .route("/reports", get(endpoints::reports::get_handler))
+        .route("/held/:id/release", post(endpoints::held::release_handler))
+        .route("/held/:id/dismiss", post(endpoints::held::dismiss_handler))
+        .route("/jobs", get(endpoints::jobs::get_handler))
         .route("/xmls", get(endpoints::xmls::get_handler))
+        .route("/xmls/schema", get(endpoints::xmls::get_schema_handler))
+        .route("/xmls/:id/errors", get(endpoints::xmls::get_errors_handler))
+        .route("/xmls/:id/rows", get(endpoints::xmls::get_rows_handler))
+        .route("/xmls/:id/cancel", post(endpoints::xmls::cancel_handler))
+        .route(
+            "/xmls/:id/progress",
+            get(endpoints::xmls::get_progress_handler),
+        )
         .layer(Extension(state))
         .layer(DefaultBodyLimit::max(max_upload))
         .layer(cors);
@@ -75,24 +104,10 @@ async fn setup_state() -> State {
         }
     };
 
-    State {
-        pool: db_client.pool.unwrap(),
-    }
+    State::new(db_client.pool.unwrap(), Arc::new(MethodClient::from_env()))
 }
 
 fn get_max_upload_size() -> usize {
-    let default = 128 * 1024 * 1024 as usize;
-    match env::var("MAX_UPLOAD") {
-        Ok(size) => match size.parse::<usize>() {
-            Ok(size_as_usize) => size_as_usize,
-            Err(_) => {
-                warn!("Environment Variable MAX_UPLOAD with value {} could not be parsed as usize defaulting to 128MB", size);
-                default
-            }
-        },
-        Err(_) => {
-            warn!("Environment Variable MAX_UPLOAD not set, defaulting to 128MB");
-            default
-        }
-    }
+    // Uploads are spooled to disk rather than held in memory, so this only bounds disk usage
+    positive_or("MAX_UPLOAD", 1024 * 1024 * 1024)
 }
@@ -1,4 +1,5 @@
 use envconfig::Envconfig;
+use log::{debug, info};
 use sqlx::mysql::MySqlConnectOptions;
 use sqlx::{MySql, MySqlPool, Pool};
 
@@ -6,6 +7,144 @@ pub struct DBClient {
     pub(crate) pool: Option<Pool<MySql>>,
 }
 
+/// Tables added after the first release, with the statement that creates them. They run before
+/// [COLUMN_MIGRATIONS], which may add columns to them
+const TABLE_MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS RowErrors ( \
+     Id INT UNSIGNED AUTO_INCREMENT NOT NULL, \
+     XmlId INT UNSIGNED NOT NULL, \
+     RowIndex INT UNSIGNED NOT NULL, \
+     Line INT UNSIGNED, \
+     ColumnNumber INT UNSIGNED, \
+     Element VARCHAR(255), \
+     Reason TEXT, \
+     PRIMARY KEY(Id), \
+     FOREIGN KEY (XmlId) REFERENCES XmlParse(Id))",
+    "CREATE TABLE IF NOT EXISTS HeldPayments ( \
+     Id INT UNSIGNED AUTO_INCREMENT NOT NULL, \
+     XmlId INT UNSIGNED NOT NULL, \
+     RowIndex INT UNSIGNED NOT NULL, \
+     EmployeeId VARCHAR(255) NOT NULL, \
+     PayorId VARCHAR(255) NOT NULL, \
+     PayeeId VARCHAR(255) NOT NULL, \
+     Amount INT UNSIGNED NOT NULL, \
+     Reason TEXT, \
+     Status VARCHAR(255) NOT NULL, \
+     MethodId VARCHAR(255), \
+     PRIMARY KEY(Id), \
+     FOREIGN KEY (XmlId) REFERENCES XmlParse(Id), \
+     FOREIGN KEY (EmployeeId) REFERENCES Employees(MethodId), \
+     FOREIGN KEY (PayorId) REFERENCES Payors(MethodId), \
+     FOREIGN KEY (PayeeId) REFERENCES Payees(MethodId))",
+    "CREATE TABLE IF NOT EXISTS TransactionRows ( \
+     Id INT UNSIGNED AUTO_INCREMENT NOT NULL, \
+     XmlId INT UNSIGNED NOT NULL, \
+     RowIndex INT UNSIGNED NOT NULL, \
+     RawValues TEXT, \
+     Status VARCHAR(255) NOT NULL, \
+     EmployeeId VARCHAR(255), \
+     PayorId VARCHAR(255), \
+     PayeeId VARCHAR(255), \
+     AddressId INT UNSIGNED, \
+     PaymentId VARCHAR(255), \
+     Error TEXT, \
+     MethodError TEXT, \
+     CreatedAt VARCHAR(255), \
+     UpdatedAt VARCHAR(255), \
+     PRIMARY KEY(Id), \
+     UNIQUE (XmlId, RowIndex), \
+     FOREIGN KEY (XmlId) REFERENCES XmlParse(Id))",
+    "CREATE TABLE IF NOT EXISTS Jobs ( \
+     Id INT UNSIGNED AUTO_INCREMENT NOT NULL, \
+     Kind VARCHAR(255) NOT NULL, \
+     XmlId INT UNSIGNED, \
+     Status VARCHAR(255) NOT NULL, \
+     Attempts INT UNSIGNED NOT NULL DEFAULT 0, \
+     MaxAttempts INT UNSIGNED NOT NULL, \
+     LeaseOwner VARCHAR(255), \
+     LeaseExpiresAt BIGINT UNSIGNED, \
+     RunAfter BIGINT UNSIGNED, \
+     Error TEXT, \
+     CreatedAt VARCHAR(255), \
+     UpdatedAt VARCHAR(255), \
+     PRIMARY KEY(Id), \
+     INDEX (Status, LeaseExpiresAt), \
+     FOREIGN KEY (XmlId) REFERENCES XmlParse(Id))",
+    "CREATE TABLE IF NOT EXISTS PaymentAttempts ( \
+     Id INT UNSIGNED AUTO_INCREMENT NOT NULL, \
+     IdempotencyKey VARCHAR(255) NOT NULL, \
+     XmlId INT UNSIGNED, \
+     RowIndex INT UNSIGNED, \
+     Status VARCHAR(255) NOT NULL, \
+     PaymentId VARCHAR(255), \
+     CreatedAt VARCHAR(255), \
+     UpdatedAt VARCHAR(255), \
+     PRIMARY KEY(Id), \
+     UNIQUE (IdempotencyKey), \
+     FOREIGN KEY (XmlId) REFERENCES XmlParse(Id))",
+];
+
+/// Columns added after a table was first created, with the statement that adds them. init.sql
+/// only runs on an empty volume, so a DB created before a column existed gets it from [migrate]
+const COLUMN_MIGRATIONS: &[(&str, &str, &str)] = &[
+    (
+        "XmlParse",
+        "ContentHash",
+        "ALTER TABLE XmlParse ADD COLUMN ContentHash CHAR(64), ADD INDEX (ContentHash)",
+    ),
+    (
+        "XmlParse",
+        "DuplicateOf",
+        "ALTER TABLE XmlParse ADD COLUMN DuplicateOf INT UNSIGNED, \
+         ADD FOREIGN KEY (DuplicateOf) REFERENCES XmlParse(Id)",
+    ),
+    (
+        "XmlParse",
+        "OverrideReason",
+        "ALTER TABLE XmlParse ADD COLUMN OverrideReason TEXT",
+    ),
+    (
+        "XmlParse",
+        "ActiveContentHash",
+        "ALTER TABLE XmlParse ADD COLUMN ActiveContentHash CHAR(64) \
+         AS (IF(DuplicateOf IS NULL AND Status <> 'Failed', ContentHash, NULL)) STORED, \
+         ADD UNIQUE (ActiveContentHash)",
+    ),
+    // Earlier payments count as made when the column was added, so they are still held against
+    // for a full duplicate window rather than missed
+    (
+        "Transactions",
+        "CreatedAt",
+        "ALTER TABLE Transactions ADD COLUMN CreatedAt TIMESTAMP DEFAULT CURRENT_TIMESTAMP, \
+         ADD INDEX (EmployeeId, CreatedAt)",
+    ),
+    (
+        "XmlParse",
+        "Format",
+        "ALTER TABLE XmlParse ADD COLUMN Format VARCHAR(32)",
+    ),
+    (
+        "XmlParse",
+        "Profile",
+        "ALTER TABLE XmlParse ADD COLUMN Profile VARCHAR(255)",
+    ),
+    (
+        "XmlParse",
+        "Checkpoint",
+        "ALTER TABLE XmlParse ADD COLUMN Checkpoint INT UNSIGNED",
+    ),
+    (
+        "Transactions",
+        "RowIndex",
+        "ALTER TABLE Transactions ADD COLUMN RowIndex INT UNSIGNED",
+    ),
+    (
+        "Jobs",
+        "RunAfter",
+        "ALTER TABLE Jobs ADD COLUMN RunAfter BIGINT UNSIGNED",
+    ),
+];
+
 #[derive(Envconfig)]
 struct DBConfig {
     #[envconfig(from = "DB_HOST")]
@@ -21,6 +160,10 @@ struct DBConfig {
 }
 
 impl DBClient {
+    pub fn pool(&self) -> Option<&Pool<MySql>> {
+        self.pool.as_ref()
+    }
+
     fn new() -> Self {
         DBClient { pool: None }
     }
@@ -41,5 +184,35 @@ impl DBClient {
 pub async fn create_from_env() -> Result<DBClient, sqlx::Error> {
     let mut client = DBClient::new();
     client.init().await?;
+    migrate(client.pool().expect("Pool was initialized")).await?;
     Ok(client)
 }
+
+/// Adds any of [TABLE_MIGRATIONS] and [COLUMN_MIGRATIONS] the DB doesn't have yet. MySQL 5.7 has
+/// no `ADD COLUMN IF NOT EXISTS`, so each column is looked up first
+pub async fn migrate(pool: &Pool<MySql>) -> Result<(), sqlx::Error> {
+    for statement in TABLE_MIGRATIONS {
+        debug!("Executing query: {}", statement);
+        sqlx::query(statement).execute(pool).await?;
+    }
+    for (table, column, statement) in COLUMN_MIGRATIONS {
+        let query = "SELECT COUNT(*) FROM information_schema.COLUMNS \
+                     WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ? AND COLUMN_NAME = ?";
+        debug!(
+            "Executing query: {}, with bindings {:?}",
+            query,
+            (table, column)
+        );
+        let found: i64 = sqlx::query_scalar(query)
+            .bind(table)
+            .bind(column)
+            .fetch_one(pool)
+            .await?;
+        if found == 0 {
+            info!("Adding column {} to {}", column, table);
+            debug!("Executing query: {}", statement);
+            sqlx::query(statement).execute(pool).await?;
+        }
+    }
+    Ok(())
+}
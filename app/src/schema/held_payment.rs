@@ -0,0 +1,57 @@
+use crate::schema::money::Money;
+use crate::schema::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// A payment flagged as a likely duplicate, which is not paid until someone releases it
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[sqlx(rename_all = "PascalCase")]
+pub struct HeldPayment {
+    pub id: Option<u64>,
+    pub xml_id: u64,
+    pub row_index: u64, // 1-based position of the <row> in the file
+    pub employee_id: String,
+    pub payor_id: String,
+    pub payee_id: String,
+    pub amount: Money,
+    pub reason: String,
+    pub status: String,            // Held, Released or Dismissed
+    pub method_id: Option<String>, // The payment made once released
+}
+
+impl HeldPayment {
+    pub const HELD: &'static str = "Held";
+    pub const RELEASED: &'static str = "Released";
+    pub const DISMISSED: &'static str = "Dismissed";
+
+    pub fn new(row_index: u64, transaction: &Transaction, reason: String) -> Self {
+        Self {
+            id: None,
+            xml_id: transaction.xml_id.expect("Xml Id was set"),
+            row_index,
+            employee_id: transaction
+                .employee_id
+                .clone()
+                .expect("Employee id was set"),
+            payor_id: transaction.payor_id.clone().expect("Payor id was set"),
+            payee_id: transaction.payee_id.clone().expect("Payee id was set"),
+            amount: transaction.amount.expect("Amount was set"),
+            reason,
+            status: String::from(Self::HELD),
+            method_id: None,
+        }
+    }
+
+    /// The payment that was held back
+    pub fn to_transaction(&self) -> Transaction {
+        Transaction {
+            method_id: None,
+            employee_id: Some(self.employee_id.clone()),
+            payee_id: Some(self.payee_id.clone()),
+            payor_id: Some(self.payor_id.clone()),
+            xml_id: Some(self.xml_id),
+            amount: Some(self.amount),
+            row_index: Some(self.row_index),
+        }
+    }
+}
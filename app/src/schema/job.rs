@@ -0,0 +1,72 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::time::SystemTime;
+
+/// Background work waiting for or being run by a worker, see [crate::utility::jobs]
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[sqlx(rename_all = "PascalCase")]
+pub struct Job {
+    pub id: Option<u64>,
+    pub kind: String, // What the job does, only Import so far
+    pub xml_id: Option<u64>,
+    pub status: String, // Enqueued, Running, Succeeded or Failed
+    pub attempts: u64,
+    pub max_attempts: u64,
+    pub lease_owner: Option<String>,   // Worker running the job
+    pub lease_expires_at: Option<u64>, // Unix seconds by the DB's clock, shared by every replica
+    pub run_after: Option<u64>,        // Unix seconds, a failed job isn't retried before then
+    pub error: Option<String>,         // Why the last attempt failed
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl Job {
+    pub const IMPORT: &'static str = "Import";
+
+    pub const ENQUEUED: &'static str = "Enqueued";
+    pub const RUNNING: &'static str = "Running";
+    pub const SUCCEEDED: &'static str = "Succeeded";
+    pub const FAILED: &'static str = "Failed";
+
+    pub fn import(xml_id: u64, max_attempts: u64) -> Self {
+        let time: DateTime<Utc> = SystemTime::now().into();
+        let time = time.format("%d/%m/%Y %T").to_string();
+
+        Self {
+            id: None,
+            kind: String::from(Self::IMPORT),
+            xml_id: Some(xml_id),
+            status: String::from(Self::ENQUEUED),
+            attempts: 0,
+            max_attempts,
+            lease_owner: None,
+            lease_expires_at: None,
+            run_after: None,
+            error: None,
+            created_at: time.clone(),
+            updated_at: time,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_start_enqueued_and_unclaimed() {
+        let job = Job::import(7, 3);
+        assert_eq!(job.id, None);
+        assert_eq!(job.kind, Job::IMPORT);
+        assert_eq!(job.xml_id, Some(7));
+        assert_eq!(job.status, Job::ENQUEUED);
+        assert_eq!(job.attempts, 0);
+        assert_eq!(job.max_attempts, 3);
+        assert_eq!(job.lease_owner, None);
+        assert_eq!(job.lease_expires_at, None);
+        assert_eq!(job.run_after, None);
+        assert_eq!(job.error, None);
+        assert_eq!(job.created_at, job.updated_at);
+    }
+}
@@ -1,9 +1,15 @@
 use crate::schema::address::Address;
 use crate::schema::employee::Employee;
+use crate::schema::held_payment::HeldPayment;
+use crate::schema::job::Job;
+use crate::schema::money::Money;
 use crate::schema::payee::Payee;
+use crate::schema::payment_attempt::PaymentAttempt;
 use crate::schema::payor::Payor;
+use crate::schema::row_error::RowError;
 use crate::schema::transaction::Transaction;
-use crate::schema::xml_parse::XmlParse;
+use crate::schema::transaction_row::{RowResult, TransactionRow};
+use crate::schema::xml_parse::{XmlParse, XmlStatus};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use log::debug;
@@ -16,33 +22,44 @@ use std::fmt::Display;
 use std::time::SystemTime;
 
 pub mod address;
-pub(crate) mod db;
+pub mod db;
 pub mod employee;
+pub mod held_payment;
+pub mod job;
+pub mod money;
 pub mod payee;
+pub mod payment_attempt;
 pub mod payor;
+pub mod row_error;
 pub mod transaction;
+pub mod transaction_row;
 pub mod xml_parse;
 
+/// How many values [CRUD::get_in] binds to one query, well under MySQL's limit of 65,535
+const GET_IN_CHUNK: usize = 1000;
+
 impl From<SqlString> for String {
     fn from(value: SqlString) -> Self {
-        value.0
+        value.to_string()
     }
 }
 
+/// A value for a query, None is SQL NULL
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
-pub struct SqlString(String);
+pub struct SqlString(Option<String>);
 
 impl Display for SqlString {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        match &self.0 {
+            Some(value) => write!(f, "{}", value),
+            None => write!(f, "NULL"),
+        }
     }
 }
 
 impl From<String> for SqlString {
     fn from(value: String) -> Self {
-        Self {
-            0: format!("{}", value),
-        }
+        Self(Some(value))
     }
 }
 
@@ -55,9 +72,13 @@ impl From<&str> for SqlString {
 
 impl From<u64> for SqlString {
     fn from(value: u64) -> Self {
-        Self {
-            0: format!("{}", value),
-        }
+        Self(Some(format!("{}", value)))
+    }
+}
+
+impl From<Money> for SqlString {
+    fn from(value: Money) -> Self {
+        Self::from(value.cents())
     }
 }
 
@@ -65,9 +86,7 @@ impl<T: Into<SqlString>> From<Option<T>> for SqlString {
     fn from(value: Option<T>) -> Self {
         match value {
             Some(val) => val.into(),
-            None => Self {
-                0: "NULL".to_string(),
-            },
+            None => Self(None),
         }
     }
 }
@@ -108,6 +127,8 @@ where
         Ok(result)
     }
 
+    /// Rows whose fields are in the given values. The values are looked up [GET_IN_CHUNK] at a
+    /// time so any number can be passed, callers should still leave out repeats
     async fn get_in(
         pool: &Pool<MySql>,
         where_in_clauses: HashMap<&str, Vec<String>>,
@@ -115,17 +136,46 @@ where
     where
         KeyType: Display + Into<SqlString>,
     {
-        let mut query_builder = SqlBuilder::select_from(Self::TABLE_NAME);
-
-        for where_clause in where_in_clauses {
-            query_builder
-                .and_where_in_query(where_clause.0, format!("'{}'", where_clause.1.join("', '")));
+        // Nothing can be in an empty list, and `IN ()` isn't valid SQL
+        if where_in_clauses.values().any(Vec::is_empty) {
+            return Ok(vec![]);
+        }
+        // Every combination of one chunk of each field, there is usually only the one field
+        let mut chunked: Vec<Vec<(&str, &[String])>> = vec![vec![]];
+        for (field, values) in &where_in_clauses {
+            chunked = chunked
+                .into_iter()
+                .flat_map(|clauses| {
+                    values.chunks(GET_IN_CHUNK).map(move |chunk| {
+                        let mut clauses = clauses.clone();
+                        clauses.push((*field, chunk));
+                        clauses
+                    })
+                })
+                .collect();
         }
 
-        let query = query_builder.fields(&["*"]).sql().unwrap();
-        debug!("Executing query: {}", query);
-
-        let result: Vec<Self> = sqlx::query_as(query.as_str()).fetch_all(pool).await?;
+        let mut result: Vec<Self> = vec![];
+        for clauses in chunked {
+            let mut query_builder = SqlBuilder::select_from(Self::TABLE_NAME);
+            let mut bindings: Vec<&String> = vec![];
+
+            for (field, values) in clauses {
+                let placeholders = vec!["?"; values.len()];
+                query_builder.and_where_in(field, placeholders.as_slice());
+                bindings.extend(values);
+            }
+
+            let query = query_builder.fields(&["*"]).sql().unwrap();
+            debug!("Executing query: {}, with bindings {:?}", query, bindings);
+
+            let mut query = sqlx::query_as(query.as_str());
+            for binding in bindings {
+                query = query.bind(binding);
+            }
+            let rows: Vec<Self> = query.fetch_all(pool).await?;
+            result.extend(rows);
+        }
         Ok(result)
     }
 
@@ -160,8 +210,8 @@ where
         debug!("Executing query: {}, with bindings {:?}", query, values);
         let mut query_builder = sqlx::query(&query);
 
-        for value in &values {
-            query_builder = query_builder.bind(value.clone().0);
+        for value in values {
+            query_builder = query_builder.bind(value.0);
         }
 
         let result = query_builder.execute(pool).await?;
@@ -184,49 +234,146 @@ impl CRUD<u64> for XmlParse {
     }
 
     fn get_all_fields() -> Vec<&'static str> {
-        vec!["Filename", "Status", "StartedAt", "FinishedAt"]
+        vec![
+            "Filename",
+            "Status",
+            "StartedAt",
+            "FinishedAt",
+            "ContentHash",
+            "DuplicateOf",
+            "OverrideReason",
+            "Format",
+            "Profile",
+            "Checkpoint",
+        ]
     }
 
     fn get_all_values(&self) -> Vec<SqlString> {
         vec![
             SqlString::from(self.clone().filename),
-            SqlString::from(self.clone().status),
+            SqlString::from(self.status.as_str()),
             SqlString::from(self.clone().started_at),
             SqlString::from(self.clone().finished_at),
+            SqlString::from(self.clone().content_hash),
+            SqlString::from(self.duplicate_of),
+            SqlString::from(self.clone().override_reason),
+            SqlString::from(self.clone().format),
+            SqlString::from(self.clone().profile),
+            SqlString::from(self.checkpoint),
         ]
     }
 }
 
 impl XmlParse {
-    pub async fn set_finished(
+    /// Moves the import to `next` if that is allowed from the status it has in the DB, which
+    /// may have been changed by another replica. Returns false without changing anything if not
+    pub async fn set_status(
         &mut self,
         pool: &Pool<MySql>,
-        status: String,
-    ) -> Result<(), sqlx::Error> {
+        next: XmlStatus,
+    ) -> Result<bool, sqlx::Error> {
         let time: DateTime<Utc> = SystemTime::now().into();
-        self.finished_at = Some(time.format("%d/%m/%Y %T").to_string());
-        self.status = status;
-
+        let finished_at = match next.is_final() {
+            true => Some(time.format("%d/%m/%Y %T").to_string()),
+            false => None,
+        };
+
+        let mut sources: Vec<&str> = XmlStatus::sources(next)
+            .into_iter()
+            .map(XmlStatus::as_str)
+            .collect();
+        if sources.contains(&XmlStatus::Queued.as_str()) {
+            sources.push("Init"); // Written before statuses were typed
+        }
         let query = SqlBuilder::update_table(Self::TABLE_NAME)
-            // Todo: Fix this
-            .set(
-                "Status",
-                format!("'{}'", SqlString::from(self.status.clone())),
-            )
-            .set(
-                "FinishedAt",
-                format!("'{}'", SqlString::from(self.finished_at.clone())),
-            )
-            .and_where_eq("Id", format!("'{}'", SqlString::from(self.id.clone())))
+            .set("Status", format!("'{}'", next))
+            .set("FinishedAt", "?")
+            .and_where_eq("Id", SqlString::from(self.id))
+            .and_where_in_quoted("Status", &sources)
             .sql()
             .unwrap();
+        debug!("Executing query: {}", query);
 
+        let result = sqlx::query(query.as_str())
+            .bind(finished_at.clone())
+            .execute(pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        self.status = next;
+        self.finished_at = finished_at;
+        Ok(true)
+    }
+
+    /// Records that every row up to `row_index` has been processed, so a resumed import can skip
+    /// past them
+    pub async fn set_checkpoint(
+        &mut self,
+        pool: &Pool<MySql>,
+        row_index: u64,
+    ) -> Result<(), sqlx::Error> {
+        let query = SqlBuilder::update_table(Self::TABLE_NAME)
+            .set("Checkpoint", SqlString::from(row_index))
+            .and_where_eq("Id", SqlString::from(self.id))
+            .sql()
+            .unwrap();
         debug!("Executing query: {}", query);
 
         sqlx::query(query.as_str()).execute(pool).await?;
+        self.checkpoint = Some(row_index);
         Ok(())
     }
 
+    pub async fn get_by_id(pool: &Pool<MySql>, id: u64) -> Result<Option<Self>, sqlx::Error> {
+        let xmls = Self::get_by(pool, HashMap::from([("Id", SqlString::from(id))])).await?;
+        Ok(xmls.into_iter().next())
+    }
+
+    /// Only the status, so a running import can cheaply check whether it was cancelled
+    pub async fn get_status(pool: &Pool<MySql>, id: u64) -> Result<XmlStatus, sqlx::Error> {
+        let query = SqlBuilder::select_from(Self::TABLE_NAME)
+            .field("Status")
+            .and_where_eq("Id", SqlString::from(id))
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        sqlx::query_scalar(query.as_str()).fetch_one(pool).await
+    }
+
+    /// How many of the import's rows have been paid, across every run of it
+    pub async fn count_transactions(pool: &Pool<MySql>, xml_id: u64) -> Result<u64, sqlx::Error> {
+        let query = SqlBuilder::select_from(Transaction::TABLE_NAME)
+            .count("*")
+            .and_where_eq("XmlId", SqlString::from(xml_id))
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        let count: i64 = sqlx::query_scalar(query.as_str()).fetch_one(pool).await?;
+        Ok(count as u64)
+    }
+
+    /// The import of a file with this content that isn't a duplicate and didn't fail, if there
+    /// is one. The DB allows only one
+    pub async fn get_by_content_hash(
+        pool: &Pool<MySql>,
+        content_hash: &str,
+    ) -> Result<Option<XmlParse>, sqlx::Error> {
+        let query = SqlBuilder::select_from(Self::TABLE_NAME)
+            .fields(&["*"])
+            .and_where_eq("ActiveContentHash", "?")
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}, with binding {}", query, content_hash);
+
+        sqlx::query_as(query.as_str())
+            .bind(content_hash)
+            .fetch_optional(pool)
+            .await
+    }
+
     // TODO: make generic, include in CRUD
     pub async fn get_all_transactions_by_xml_id(
         pool: &Pool<MySql>,
@@ -260,6 +407,7 @@ impl CRUD<String> for Transaction {
             "PayeeId",
             "XmlId",
             "Amount",
+            "RowIndex",
         ]
     }
 
@@ -271,6 +419,7 @@ impl CRUD<String> for Transaction {
             SqlString::from(self.clone().payee_id),
             SqlString::from(self.clone().xml_id),
             SqlString::from(self.clone().amount),
+            SqlString::from(self.row_index),
         ]
     }
 }
@@ -380,7 +529,7 @@ impl CRUD<u64> for Address {
     const ID_FIELD: &'static str = "Id";
 
     fn get_id(&self) -> u64 {
-        self.id.clone().expect("Id was set")
+        self.id.expect("Id was set")
     }
 
     fn get_all_fields() -> Vec<&'static str> {
@@ -396,3 +545,597 @@ impl CRUD<u64> for Address {
         ]
     }
 }
+
+impl CRUD<u64> for RowError {
+    const TABLE_NAME: &'static str = "RowErrors";
+
+    const ID_FIELD: &'static str = "Id";
+
+    fn get_id(&self) -> u64 {
+        self.id.expect("Id was set")
+    }
+
+    fn get_all_fields() -> Vec<&'static str> {
+        vec![
+            "XmlId",
+            "RowIndex",
+            "Line",
+            "ColumnNumber",
+            "Element",
+            "Reason",
+        ]
+    }
+
+    fn get_all_values(&self) -> Vec<SqlString> {
+        vec![
+            SqlString::from(self.xml_id),
+            SqlString::from(self.row_index),
+            SqlString::from(self.line),
+            SqlString::from(self.column_number),
+            SqlString::from(self.element.clone()),
+            SqlString::from(self.reason.clone()),
+        ]
+    }
+}
+
+impl RowError {
+    pub async fn get_all_by_xml_id(
+        pool: &Pool<MySql>,
+        xml_id: u64,
+    ) -> Result<Vec<RowError>, sqlx::Error> {
+        let query = SqlBuilder::select_from(RowError::TABLE_NAME)
+            .fields(&["*"])
+            .and_where_eq("XmlId", SqlString::from(xml_id))
+            .order_by("RowIndex", false)
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        let result: Vec<RowError> = sqlx::query_as(query.as_str()).fetch_all(pool).await?;
+        Ok(result)
+    }
+}
+
+impl CRUD<u64> for HeldPayment {
+    const TABLE_NAME: &'static str = "HeldPayments";
+
+    const ID_FIELD: &'static str = "Id";
+
+    fn get_id(&self) -> u64 {
+        self.id.expect("Id was set")
+    }
+
+    fn get_all_fields() -> Vec<&'static str> {
+        vec![
+            "XmlId",
+            "RowIndex",
+            "EmployeeId",
+            "PayorId",
+            "PayeeId",
+            "Amount",
+            "Reason",
+            "Status",
+            "MethodId",
+        ]
+    }
+
+    fn get_all_values(&self) -> Vec<SqlString> {
+        vec![
+            SqlString::from(self.xml_id),
+            SqlString::from(self.row_index),
+            SqlString::from(self.employee_id.clone()),
+            SqlString::from(self.payor_id.clone()),
+            SqlString::from(self.payee_id.clone()),
+            SqlString::from(self.amount),
+            SqlString::from(self.reason.clone()),
+            SqlString::from(self.status.clone()),
+            SqlString::from(self.method_id.clone()),
+        ]
+    }
+}
+
+impl HeldPayment {
+    pub async fn get_all_by_xml_id(
+        pool: &Pool<MySql>,
+        xml_id: u64,
+    ) -> Result<Vec<HeldPayment>, sqlx::Error> {
+        let query = SqlBuilder::select_from(HeldPayment::TABLE_NAME)
+            .fields(&["*"])
+            .and_where_eq("XmlId", SqlString::from(xml_id))
+            .order_by("RowIndex", false)
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        let result: Vec<HeldPayment> = sqlx::query_as(query.as_str()).fetch_all(pool).await?;
+        Ok(result)
+    }
+
+    /// Moves the payment from `from` to `to`, returns false without changing anything if it was
+    /// no longer in `from`, so two reviewers can't both release it
+    pub async fn set_status(
+        &mut self,
+        pool: &Pool<MySql>,
+        from: &str,
+        to: &str,
+        method_id: Option<String>,
+    ) -> Result<bool, sqlx::Error> {
+        let query = SqlBuilder::update_table(Self::TABLE_NAME)
+            .set("Status", "?")
+            .set("MethodId", "?")
+            .and_where_eq("Id", SqlString::from(self.id))
+            .and_where_eq("Status", "?")
+            .sql()
+            .unwrap();
+        debug!(
+            "Executing query: {}, with bindings {:?}",
+            query,
+            (to, &method_id, from)
+        );
+
+        let result = sqlx::query(query.as_str())
+            .bind(to)
+            .bind(method_id.clone())
+            .bind(from)
+            .execute(pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        self.status = to.to_string();
+        self.method_id = method_id;
+        Ok(true)
+    }
+}
+
+impl Transaction {
+    /// Payments to any of `employee_ids` made by other imports in the last `days` days
+    pub async fn get_recent_by_employees(
+        pool: &Pool<MySql>,
+        employee_ids: &[String],
+        exclude_xml_id: u64,
+        days: u64,
+    ) -> Result<Vec<Transaction>, sqlx::Error> {
+        if employee_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let placeholders = vec!["?"; employee_ids.len()];
+        let query = SqlBuilder::select_from(Transaction::TABLE_NAME)
+            .fields(&["*"])
+            .and_where_in("EmployeeId", placeholders.as_slice())
+            .and_where_ne("XmlId", SqlString::from(exclude_xml_id))
+            .and_where("CreatedAt >= NOW() - INTERVAL ? DAY")
+            .sql()
+            .unwrap();
+        debug!(
+            "Executing query: {}, with bindings {:?}",
+            query,
+            (employee_ids, days)
+        );
+
+        let mut query = sqlx::query_as(query.as_str());
+        for employee_id in employee_ids {
+            query = query.bind(employee_id);
+        }
+        let result: Vec<Transaction> = query.bind(days).fetch_all(pool).await?;
+        Ok(result)
+    }
+}
+
+impl CRUD<u64> for TransactionRow {
+    const TABLE_NAME: &'static str = "TransactionRows";
+
+    const ID_FIELD: &'static str = "Id";
+
+    fn get_id(&self) -> u64 {
+        self.id.expect("Id was set")
+    }
+
+    fn get_all_fields() -> Vec<&'static str> {
+        vec![
+            "XmlId",
+            "RowIndex",
+            "RawValues",
+            "Status",
+            "EmployeeId",
+            "PayorId",
+            "PayeeId",
+            "AddressId",
+            "PaymentId",
+            "Error",
+            "MethodError",
+            "CreatedAt",
+            "UpdatedAt",
+        ]
+    }
+
+    fn get_all_values(&self) -> Vec<SqlString> {
+        vec![
+            SqlString::from(self.xml_id),
+            SqlString::from(self.row_index),
+            SqlString::from(self.raw_values.clone()),
+            SqlString::from(self.status.clone()),
+            SqlString::from(self.employee_id.clone()),
+            SqlString::from(self.payor_id.clone()),
+            SqlString::from(self.payee_id.clone()),
+            SqlString::from(self.address_id),
+            SqlString::from(self.payment_id.clone()),
+            SqlString::from(self.error.clone()),
+            SqlString::from(self.method_error.clone()),
+            SqlString::from(self.created_at.clone()),
+            SqlString::from(self.updated_at.clone()),
+        ]
+    }
+}
+
+impl TransactionRow {
+    pub async fn get_all_by_xml_id(
+        pool: &Pool<MySql>,
+        xml_id: u64,
+    ) -> Result<Vec<TransactionRow>, sqlx::Error> {
+        let query = SqlBuilder::select_from(TransactionRow::TABLE_NAME)
+            .fields(&["*"])
+            .and_where_eq("XmlId", SqlString::from(xml_id))
+            .order_by("RowIndex", false)
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        let result: Vec<TransactionRow> = sqlx::query_as(query.as_str()).fetch_all(pool).await?;
+        Ok(result)
+    }
+
+    /// How many of the import's rows have each status
+    pub async fn count_by_status(
+        pool: &Pool<MySql>,
+        xml_id: u64,
+    ) -> Result<HashMap<String, u64>, sqlx::Error> {
+        let query = SqlBuilder::select_from(TransactionRow::TABLE_NAME)
+            .field("Status")
+            .field("COUNT(*)")
+            .and_where_eq("XmlId", SqlString::from(xml_id))
+            .group_by("Status")
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        let counts: Vec<(String, i64)> = sqlx::query_as(query.as_str()).fetch_all(pool).await?;
+        Ok(counts
+            .into_iter()
+            .map(|(status, count)| (status, count as u64))
+            .collect())
+    }
+
+    /// Records what became of row `row_index` of `xml_id`. Ids the result doesn't have are left
+    /// as they were, so releasing a held row keeps the address it was resolved to
+    pub async fn set_result(
+        pool: &Pool<MySql>,
+        xml_id: u64,
+        row_index: u64,
+        result: &RowResult,
+    ) -> Result<(), sqlx::Error> {
+        let time: DateTime<Utc> = SystemTime::now().into();
+
+        let query = SqlBuilder::update_table(Self::TABLE_NAME)
+            .set("Status", "?")
+            .set("EmployeeId", "COALESCE(?, EmployeeId)")
+            .set("PayorId", "COALESCE(?, PayorId)")
+            .set("PayeeId", "COALESCE(?, PayeeId)")
+            .set("AddressId", "COALESCE(?, AddressId)")
+            .set("PaymentId", "COALESCE(?, PaymentId)")
+            .set("Error", "?")
+            .set("MethodError", "?")
+            .set("UpdatedAt", "?")
+            .and_where_eq("XmlId", SqlString::from(xml_id))
+            .and_where_eq("RowIndex", SqlString::from(row_index))
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}, with result {:?}", query, result);
+
+        sqlx::query(query.as_str())
+            .bind(result.status)
+            .bind(result.employee_id.clone())
+            .bind(result.payor_id.clone())
+            .bind(result.payee_id.clone())
+            .bind(result.address_id)
+            .bind(result.payment_id.clone())
+            .bind(result.error.clone())
+            .bind(result.method_error.clone())
+            .bind(time.format("%d/%m/%Y %T").to_string())
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+impl CRUD<u64> for Job {
+    const TABLE_NAME: &'static str = "Jobs";
+
+    const ID_FIELD: &'static str = "Id";
+
+    fn get_id(&self) -> u64 {
+        self.id.expect("Id was set")
+    }
+
+    fn get_all_fields() -> Vec<&'static str> {
+        vec![
+            "Kind",
+            "XmlId",
+            "Status",
+            "Attempts",
+            "MaxAttempts",
+            "LeaseOwner",
+            "LeaseExpiresAt",
+            "RunAfter",
+            "Error",
+            "CreatedAt",
+            "UpdatedAt",
+        ]
+    }
+
+    fn get_all_values(&self) -> Vec<SqlString> {
+        vec![
+            SqlString::from(self.kind.clone()),
+            SqlString::from(self.xml_id),
+            SqlString::from(self.status.clone()),
+            SqlString::from(self.attempts),
+            SqlString::from(self.max_attempts),
+            SqlString::from(self.lease_owner.clone()),
+            SqlString::from(self.lease_expires_at),
+            SqlString::from(self.run_after),
+            SqlString::from(self.error.clone()),
+            SqlString::from(self.created_at.clone()),
+            SqlString::from(self.updated_at.clone()),
+        ]
+    }
+}
+
+impl Job {
+    /// Jobs nobody is running, either waiting and due or left behind by a worker that stopped
+    /// renewing its lease
+    const CLAIMABLE: &'static str = "((Status = 'Enqueued' \
+        AND (RunAfter IS NULL OR RunAfter <= UNIX_TIMESTAMP())) \
+        OR (Status = 'Running' AND LeaseExpiresAt < UNIX_TIMESTAMP()))";
+
+    pub async fn get_all(pool: &Pool<MySql>) -> Result<Vec<Job>, sqlx::Error> {
+        let query = SqlBuilder::select_from(Job::TABLE_NAME)
+            .fields(&["*"])
+            .order_desc("Id")
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        let result: Vec<Job> = sqlx::query_as(query.as_str()).fetch_all(pool).await?;
+        Ok(result)
+    }
+
+    /// The oldest jobs that could be claimed, another worker may claim them first
+    pub async fn get_claimable(pool: &Pool<MySql>, limit: u64) -> Result<Vec<Job>, sqlx::Error> {
+        let query = SqlBuilder::select_from(Job::TABLE_NAME)
+            .fields(&["*"])
+            .and_where(Self::CLAIMABLE)
+            .order_by("Id", false)
+            .limit(limit)
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        let result: Vec<Job> = sqlx::query_as(query.as_str()).fetch_all(pool).await?;
+        Ok(result)
+    }
+
+    /// Takes the job for `owner` for the next `lease_secs` seconds, returns false if another
+    /// worker got to it first
+    pub async fn claim(
+        &mut self,
+        pool: &Pool<MySql>,
+        owner: &str,
+        lease_secs: u64,
+    ) -> Result<bool, sqlx::Error> {
+        let time: DateTime<Utc> = SystemTime::now().into();
+        let query = SqlBuilder::update_table(Self::TABLE_NAME)
+            .set("Status", format!("'{}'", Self::RUNNING))
+            .set("Attempts", "Attempts + 1")
+            .set("LeaseOwner", "?")
+            .set("LeaseExpiresAt", "UNIX_TIMESTAMP() + ?")
+            .set("UpdatedAt", "?")
+            .and_where_eq("Id", SqlString::from(self.id))
+            .and_where(Self::CLAIMABLE)
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        let result = sqlx::query(query.as_str())
+            .bind(owner)
+            .bind(lease_secs)
+            .bind(time.format("%d/%m/%Y %T").to_string())
+            .execute(pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        self.status = String::from(Self::RUNNING);
+        self.attempts += 1;
+        self.lease_owner = Some(owner.to_string());
+        Ok(true)
+    }
+
+    /// Inserts the job already claimed by `owner` for the next `lease_secs` seconds, so no
+    /// worker can take it before `owner` runs it
+    pub async fn insert_claimed(
+        &mut self,
+        pool: &Pool<MySql>,
+        owner: &str,
+        lease_secs: u64,
+    ) -> Result<u64, sqlx::Error> {
+        self.status = String::from(Self::RUNNING);
+        self.attempts += 1;
+        self.lease_owner = Some(owner.to_string());
+
+        let fields = Self::get_all_fields();
+        let placeholders: Vec<&str> = fields
+            .iter()
+            .map(|field| match *field {
+                "LeaseExpiresAt" => "UNIX_TIMESTAMP() + ?",
+                _ => "?",
+            })
+            .collect();
+        let query = SqlBuilder::insert_into(Self::TABLE_NAME)
+            .fields(fields.as_slice())
+            .values(placeholders.as_slice())
+            .sql()
+            .unwrap();
+        let values = self.get_all_values();
+        debug!("Executing query: {}, with bindings {:?}", query, values);
+
+        let mut query_builder = sqlx::query(&query);
+        for (field, value) in fields.iter().zip(values) {
+            query_builder = match *field {
+                "LeaseExpiresAt" => query_builder.bind(lease_secs),
+                _ => query_builder.bind(value.0),
+            };
+        }
+        let result = query_builder.execute(pool).await?;
+        self.id = Some(result.last_insert_id());
+        Ok(result.last_insert_id())
+    }
+
+    /// Extends the lease of a running job, returns false if it is no longer this worker's
+    pub async fn renew(&self, pool: &Pool<MySql>, lease_secs: u64) -> Result<bool, sqlx::Error> {
+        let query = SqlBuilder::update_table(Self::TABLE_NAME)
+            .set("LeaseExpiresAt", "UNIX_TIMESTAMP() + ?")
+            .and_where_eq("Id", SqlString::from(self.id))
+            .and_where_eq("Status", format!("'{}'", Self::RUNNING))
+            .and_where_eq("LeaseOwner", "?")
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        let result = sqlx::query(query.as_str())
+            .bind(lease_secs)
+            .bind(self.lease_owner.clone())
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Releases the job as `status`, Enqueued to have it tried again once `delay_secs` have
+    /// passed. Returns false if it was no longer this worker's
+    pub async fn release(
+        &mut self,
+        pool: &Pool<MySql>,
+        status: &str,
+        error: Option<String>,
+        delay_secs: u64,
+    ) -> Result<bool, sqlx::Error> {
+        let time: DateTime<Utc> = SystemTime::now().into();
+        let query = SqlBuilder::update_table(Self::TABLE_NAME)
+            .set("Status", "?")
+            .set("Error", "?")
+            .set("LeaseOwner", "NULL")
+            .set("LeaseExpiresAt", "NULL")
+            .set("RunAfter", "UNIX_TIMESTAMP() + ?")
+            .set("UpdatedAt", "?")
+            .and_where_eq("Id", SqlString::from(self.id))
+            .and_where_eq("LeaseOwner", "?")
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        let result = sqlx::query(query.as_str())
+            .bind(status)
+            .bind(error.clone())
+            .bind(delay_secs)
+            .bind(time.format("%d/%m/%Y %T").to_string())
+            .bind(self.lease_owner.clone())
+            .execute(pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        self.status = status.to_string();
+        self.error = error;
+        self.lease_owner = None;
+        self.lease_expires_at = None;
+        Ok(true)
+    }
+}
+
+impl CRUD<u64> for PaymentAttempt {
+    const TABLE_NAME: &'static str = "PaymentAttempts";
+
+    const ID_FIELD: &'static str = "Id";
+
+    fn get_id(&self) -> u64 {
+        self.id.expect("Id was set")
+    }
+
+    fn get_all_fields() -> Vec<&'static str> {
+        vec![
+            "IdempotencyKey",
+            "XmlId",
+            "RowIndex",
+            "Status",
+            "PaymentId",
+            "CreatedAt",
+            "UpdatedAt",
+        ]
+    }
+
+    fn get_all_values(&self) -> Vec<SqlString> {
+        vec![
+            SqlString::from(self.idempotency_key.clone()),
+            SqlString::from(self.xml_id),
+            SqlString::from(self.row_index),
+            SqlString::from(self.status.clone()),
+            SqlString::from(self.payment_id.clone()),
+            SqlString::from(self.created_at.clone()),
+            SqlString::from(self.updated_at.clone()),
+        ]
+    }
+}
+
+impl PaymentAttempt {
+    pub async fn get_by_key(
+        pool: &Pool<MySql>,
+        idempotency_key: &str,
+    ) -> Result<Option<PaymentAttempt>, sqlx::Error> {
+        let query = SqlBuilder::select_from(Self::TABLE_NAME)
+            .fields(&["*"])
+            .and_where_eq("IdempotencyKey", "?")
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        sqlx::query_as(query.as_str())
+            .bind(idempotency_key)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// Records the payment Method made for `idempotency_key`
+    pub async fn set_paid(
+        pool: &Pool<MySql>,
+        idempotency_key: &str,
+        payment_id: &str,
+    ) -> Result<(), sqlx::Error> {
+        let time: DateTime<Utc> = SystemTime::now().into();
+
+        let query = SqlBuilder::update_table(Self::TABLE_NAME)
+            .set("Status", format!("'{}'", Self::PAID))
+            .set("PaymentId", "?")
+            .set("UpdatedAt", "?")
+            .and_where_eq("IdempotencyKey", "?")
+            .sql()
+            .unwrap();
+        debug!("Executing query: {}", query);
+
+        sqlx::query(query.as_str())
+            .bind(payment_id)
+            .bind(time.format("%d/%m/%Y %T").to_string())
+            .bind(idempotency_key)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+use lazy_static::lazy_static;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::AddAssign;
+use std::str::FromStr;
+
+use crate::utility::env::parse_or;
+
+lazy_static! {
+    static ref MAX_PAYMENT: Money = get_max_payment();
+}
+
+/// An amount of USD held as integer cents, so it never goes through floating point
+#[derive(
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    sqlx::Type,
+)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct Money(u64);
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum MoneyError {
+    #[error("amount is empty")]
+    Empty,
+    #[error("negative amounts are not allowed")]
+    Negative,
+    #[error("amount must be greater than $0.00")]
+    Zero,
+    #[error("amounts can have at most two decimal places")]
+    TooManyDecimals,
+    #[error("thousands separators must separate groups of three digits")]
+    MisplacedSeparator,
+    #[error("'{0}' is not a valid character in an amount")]
+    InvalidCharacter(char),
+    #[error("amount is too large")]
+    Overflow,
+    #[error("{0} exceeds the maximum payment of {1}")]
+    ExceedsCeiling(Money, Money),
+}
+
+impl Money {
+    /// The most the `Amount` columns can hold, they are `INT UNSIGNED`
+    pub const MAX_STORABLE: Money = Money::from_cents(u32::MAX as u64);
+
+    pub const fn from_cents(cents: u64) -> Self {
+        Self(cents)
+    }
+
+    pub fn cents(&self) -> u64 {
+        self.0
+    }
+
+    /// Parses a payment amount, which unlike a plain [Money] must be non-zero and below the
+    /// configured `MAX_PAYMENT_AMOUNT`
+    pub fn parse_amount(text: &str) -> Result<Self, MoneyError> {
+        Self::parse_bounded(text, *MAX_PAYMENT)
+    }
+
+    pub fn parse_bounded(text: &str, ceiling: Money) -> Result<Self, MoneyError> {
+        let money = text.parse::<Money>()?;
+        if money.0 == 0 {
+            return Err(MoneyError::Zero);
+        }
+        if money > ceiling {
+            return Err(MoneyError::ExceedsCeiling(money, ceiling));
+        }
+        Ok(money)
+    }
+}
+
+impl FromStr for Money {
+    type Err = MoneyError;
+
+    /// Accepts `1234.5`, `$1,234.50` and similar, anything that would need rounding is rejected
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        if text.starts_with('-') || (text.starts_with('(') && text.ends_with(')')) {
+            return Err(MoneyError::Negative);
+        }
+        let text = text.strip_prefix('$').unwrap_or(text).trim_start();
+        if text.starts_with('-') {
+            return Err(MoneyError::Negative);
+        }
+        if text.is_empty() {
+            return Err(MoneyError::Empty);
+        }
+
+        let (whole, fraction) = match text.split_once('.') {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (text, None),
+        };
+
+        if let Some(c) = whole.chars().find(|c| !c.is_ascii_digit() && *c != ',') {
+            return Err(MoneyError::InvalidCharacter(c));
+        }
+        if whole.contains(',') {
+            let mut groups = whole.split(',');
+            let first = groups.next().unwrap_or("");
+            if first.is_empty() || first.len() > 3 || groups.any(|group| group.len() != 3) {
+                return Err(MoneyError::MisplacedSeparator);
+            }
+        }
+
+        let mut cents: u64 = 0;
+        for digit in whole.chars().filter(char::is_ascii_digit) {
+            cents = cents
+                .checked_mul(10)
+                .and_then(|c| c.checked_add(u64::from(digit as u8 - b'0')))
+                .ok_or(MoneyError::Overflow)?;
+        }
+        cents = cents.checked_mul(100).ok_or(MoneyError::Overflow)?;
+
+        if let Some(fraction) = fraction {
+            if let Some(c) = fraction.chars().find(|c| !c.is_ascii_digit()) {
+                return Err(MoneyError::InvalidCharacter(c));
+            }
+            let fraction_cents = match fraction.len() {
+                0 if whole.is_empty() => return Err(MoneyError::Empty),
+                0 => 0,
+                1 => fraction.parse::<u64>().map_err(|_| MoneyError::Overflow)? * 10,
+                2 => fraction.parse::<u64>().map_err(|_| MoneyError::Overflow)?,
+                _ => return Err(MoneyError::TooManyDecimals),
+            };
+            cents = cents
+                .checked_add(fraction_cents)
+                .ok_or(MoneyError::Overflow)?;
+        }
+
+        Ok(Money(cents))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "${}.{:02}", self.0 / 100, self.0 % 100)
+    }
+}
+
+impl AddAssign for Money {
+    /// Totals saturate rather than overflow, no single total can get near `u64::MAX` cents
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 = self.0.saturating_add(rhs.0);
+    }
+}
+
+fn get_max_payment() -> Money {
+    capped(parse_or("MAX_PAYMENT_AMOUNT", Money::from_cents(5_000_000))) // $50,000.00
+}
+
+/// Keeps a configured ceiling within what MySQL can store, larger payments would be truncated
+fn capped(max: Money) -> Money {
+    if max > Money::MAX_STORABLE {
+        warn!(
+            "MAX_PAYMENT_AMOUNT of {} is more than an Amount column holds, capping it at {}",
+            max,
+            Money::MAX_STORABLE
+        );
+        return Money::MAX_STORABLE;
+    }
+    max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<u64, MoneyError> {
+        text.parse::<Money>().map(|money| money.cents())
+    }
+
+    #[test]
+    fn parses_exact_amounts() {
+        assert_eq!(parse("8.15"), Ok(815));
+        assert_eq!(parse("$8.15"), Ok(815));
+        assert_eq!(parse("$ 8.15"), Ok(815));
+        assert_eq!(parse("1,234.50"), Ok(123_450));
+        assert_eq!(parse("$1,234,567.89"), Ok(123_456_789));
+        assert_eq!(parse("12.5"), Ok(1250));
+        assert_eq!(parse("12."), Ok(1200));
+        assert_eq!(parse(".5"), Ok(50));
+        assert_eq!(parse(" 40 "), Ok(4000));
+        assert_eq!(parse("0.00"), Ok(0));
+    }
+
+    #[test]
+    fn rejects_negative_amounts() {
+        assert_eq!(parse("-8.15"), Err(MoneyError::Negative));
+        assert_eq!(parse("$-8.15"), Err(MoneyError::Negative));
+        assert_eq!(parse("(8.15)"), Err(MoneyError::Negative));
+    }
+
+    #[test]
+    fn rejects_amounts_that_need_rounding() {
+        assert_eq!(parse("8.155"), Err(MoneyError::TooManyDecimals));
+        assert_eq!(parse("0.001"), Err(MoneyError::TooManyDecimals));
+    }
+
+    #[test]
+    fn rejects_malformed_amounts() {
+        assert_eq!(parse(""), Err(MoneyError::Empty));
+        assert_eq!(parse("$"), Err(MoneyError::Empty));
+        assert_eq!(parse("."), Err(MoneyError::Empty));
+        assert_eq!(parse("1,23.00"), Err(MoneyError::MisplacedSeparator));
+        assert_eq!(parse(",123"), Err(MoneyError::MisplacedSeparator));
+        assert_eq!(parse("1234,567"), Err(MoneyError::MisplacedSeparator));
+        assert_eq!(parse("8.1a"), Err(MoneyError::InvalidCharacter('a')));
+        assert_eq!(parse("1e5"), Err(MoneyError::InvalidCharacter('e')));
+        assert_eq!(parse("8.15 USD"), Err(MoneyError::InvalidCharacter(' ')));
+        assert_eq!(parse("184467440737095516.16"), Err(MoneyError::Overflow));
+    }
+
+    #[test]
+    fn payments_are_non_zero_and_within_the_ceiling() {
+        let ceiling = Money::from_cents(10_000);
+        assert_eq!(
+            Money::parse_bounded("100.00", ceiling),
+            Ok(Money::from_cents(10_000))
+        );
+        assert_eq!(
+            Money::parse_bounded("100.01", ceiling),
+            Err(MoneyError::ExceedsCeiling(
+                Money::from_cents(10_001),
+                ceiling
+            ))
+        );
+        assert_eq!(Money::parse_bounded("0.00", ceiling), Err(MoneyError::Zero));
+        assert_eq!(
+            Money::parse_bounded("-1", ceiling),
+            Err(MoneyError::Negative)
+        );
+    }
+
+    #[test]
+    fn ceiling_is_capped_at_what_mysql_stores() {
+        assert_eq!(
+            capped(Money::from_cents(5_000_000)),
+            Money::from_cents(5_000_000)
+        );
+        assert_eq!(capped(Money::from_cents(u64::MAX)), Money::MAX_STORABLE);
+    }
+
+    #[test]
+    fn totals_saturate() {
+        let mut total = Money::from_cents(u64::MAX - 1);
+        total += Money::from_cents(5);
+        assert_eq!(total, Money::from_cents(u64::MAX));
+    }
+
+    #[test]
+    fn displays_as_dollars() {
+        assert_eq!(Money::from_cents(123_405).to_string(), "$1234.05");
+        assert_eq!(Money::from_cents(7).to_string(), "$0.07");
+    }
+}
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
-#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, FromRow)]
 #[sqlx(rename_all = "PascalCase")]
 pub struct Payee {
     pub plaid_id: Option<String>,
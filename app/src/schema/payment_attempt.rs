@@ -0,0 +1,41 @@
+use crate::schema::transaction::Transaction;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::time::SystemTime;
+
+/// A payment sent to Method, recorded before the request so that one whose response was lost
+/// is recovered rather than paid again
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[sqlx(rename_all = "PascalCase")]
+pub struct PaymentAttempt {
+    pub id: Option<u64>,
+    pub idempotency_key: String, // See Transaction::idempotency_key
+    pub xml_id: Option<u64>,
+    pub row_index: Option<u64>,
+    pub status: String, // Pending until Method's payment id is known, then Paid
+    pub payment_id: Option<String>, // Method's id for the payment
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl PaymentAttempt {
+    pub const PENDING: &'static str = "Pending";
+    pub const PAID: &'static str = "Paid";
+
+    pub fn new(idempotency_key: String, transaction: &Transaction) -> Self {
+        let time: DateTime<Utc> = SystemTime::now().into();
+        let time = time.format("%d/%m/%Y %T").to_string();
+
+        Self {
+            id: None,
+            idempotency_key,
+            xml_id: transaction.xml_id,
+            row_index: transaction.row_index,
+            status: String::from(Self::PENDING),
+            payment_id: None,
+            created_at: time.clone(),
+            updated_at: time,
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[sqlx(rename_all = "PascalCase")]
+pub struct RowError {
+    pub id: Option<u64>,
+    pub xml_id: Option<u64>, // None for dry runs, which are never stored
+    pub row_index: u64,      // 1-based position of the <row> in the file
+    pub line: u64,
+    pub column_number: u64,
+    pub element: String,
+    pub reason: String,
+}
+
+impl RowError {
+    pub fn new(
+        xml_id: Option<u64>,
+        row_index: u64,
+        line: u64,
+        column_number: u64,
+        element: String,
+        reason: String,
+    ) -> Self {
+        Self {
+            id: None,
+            xml_id,
+            row_index,
+            line,
+            column_number,
+            element,
+            reason,
+        }
+    }
+}
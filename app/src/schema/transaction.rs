@@ -1,7 +1,9 @@
+use crate::schema::money::Money;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx::FromRow;
 
-#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, FromRow)]
 #[sqlx(rename_all = "PascalCase")]
 pub struct Transaction {
     pub method_id: Option<String>,
@@ -9,7 +11,8 @@ pub struct Transaction {
     pub payee_id: Option<String>,
     pub payor_id: Option<String>,
     pub xml_id: Option<u64>,
-    pub amount: Option<u64>, // Amount in cents
+    pub amount: Option<Money>,
+    pub row_index: Option<u64>, // 1-based position of the <row> it was paid for
 }
 
 impl Transaction {
@@ -23,6 +26,76 @@ impl Transaction {
             payor_id: None,
             xml_id: None,
             amount: None,
+            row_index: None,
+        }
+    }
+
+    /// The same for every attempt at this payment, so Method makes it at most once. Built from
+    /// the import, the row and what is paid, so a row whose content changes is a new payment
+    pub fn idempotency_key(&self) -> String {
+        let content = format!(
+            "{}:{}:{}:{}",
+            self.employee_id.clone().unwrap_or_default(),
+            self.payor_id.clone().unwrap_or_default(),
+            self.payee_id.clone().unwrap_or_default(),
+            self.amount.unwrap_or_default().cents()
+        );
+        let digest = hex::encode(Sha256::digest(content.as_bytes()));
+        format!(
+            "{}-{}-{}",
+            self.xml_id.unwrap_or_default(),
+            self.row_index.unwrap_or_default(),
+            &digest[..16]
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction() -> Transaction {
+        Transaction {
+            employee_id: Some(String::from("entity_employee")),
+            payee_id: Some(String::from("acc_payee")),
+            payor_id: Some(String::from("acc_payor")),
+            xml_id: Some(12),
+            amount: Some(Money::from_cents(815)),
+            row_index: Some(3),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keys_are_stable_across_attempts() {
+        let key = transaction().idempotency_key();
+        assert_eq!(key, transaction().idempotency_key());
+        assert!(key.starts_with("12-3-"), "{}", key);
+        assert_eq!(key.len(), "12-3-".len() + 16);
+    }
+
+    #[test]
+    fn keys_ignore_the_payment_id() {
+        let mut paid = transaction();
+        paid.method_id = Some(String::from("pmt_paid"));
+        assert_eq!(paid.idempotency_key(), transaction().idempotency_key());
+    }
+
+    #[test]
+    fn keys_change_with_the_import_row_or_content() {
+        let key = transaction().idempotency_key();
+        let changes: [fn(&mut Transaction); 6] = [
+            |t| t.xml_id = Some(13),
+            |t| t.row_index = Some(4),
+            |t| t.employee_id = Some(String::from("entity_other")),
+            |t| t.payee_id = Some(String::from("acc_other")),
+            |t| t.payor_id = Some(String::from("acc_other")),
+            |t| t.amount = Some(Money::from_cents(816)),
+        ];
+        for change in changes {
+            let mut changed = transaction();
+            change(&mut changed);
+            assert_ne!(changed.idempotency_key(), key, "{:?}", changed);
         }
     }
 }
@@ -0,0 +1,206 @@
+use crate::schema::transaction::Transaction;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::time::SystemTime;
+
+/// Every row of an import and what became of it, whether or not it was paid
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[sqlx(rename_all = "PascalCase")]
+pub struct TransactionRow {
+    pub id: Option<u64>,
+    pub xml_id: u64,
+    pub row_index: u64,     // 1-based position of the <row> in the file
+    pub raw_values: String, // JSON object of each value as it appeared in the file
+    pub status: String,     // Parsed, Invalid, Paid, Held, Failed or Dismissed
+    pub employee_id: Option<String>,
+    pub payor_id: Option<String>,
+    pub payee_id: Option<String>,
+    pub address_id: Option<u64>,
+    pub payment_id: Option<String>,
+    pub error: Option<String>,
+    pub method_error: Option<String>, // Message Method gave when it rejected a request
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl TransactionRow {
+    pub const PARSED: &'static str = "Parsed";
+    pub const INVALID: &'static str = "Invalid";
+    pub const PAID: &'static str = "Paid";
+    pub const HELD: &'static str = "Held";
+    pub const FAILED: &'static str = "Failed";
+    pub const DISMISSED: &'static str = "Dismissed";
+
+    /// A row as read from the file, `error` is why it could not be parsed
+    pub fn new(xml_id: u64, row_index: u64, raw_values: String, error: Option<String>) -> Self {
+        let time: DateTime<Utc> = SystemTime::now().into();
+        let time = time.format("%d/%m/%Y %T").to_string();
+        let status = match error {
+            Some(_) => Self::INVALID,
+            None => Self::PARSED,
+        };
+
+        Self {
+            id: None,
+            xml_id,
+            row_index,
+            raw_values,
+            status: String::from(status),
+            employee_id: None,
+            payor_id: None,
+            payee_id: None,
+            address_id: None,
+            payment_id: None,
+            error,
+            method_error: None,
+            created_at: time.clone(),
+            updated_at: time,
+        }
+    }
+}
+
+/// What processing a parsed row came to, written over the row's earlier result
+#[derive(Debug, Clone)]
+pub struct RowResult {
+    pub status: &'static str,
+    pub employee_id: Option<String>,
+    pub payor_id: Option<String>,
+    pub payee_id: Option<String>,
+    pub address_id: Option<u64>,
+    pub payment_id: Option<String>,
+    pub error: Option<String>,
+    pub method_error: Option<String>,
+}
+
+impl RowResult {
+    fn of(
+        status: &'static str,
+        transaction: Option<&Transaction>,
+        address_id: Option<u64>,
+    ) -> Self {
+        Self {
+            status,
+            employee_id: transaction.and_then(|t| t.employee_id.clone()),
+            payor_id: transaction.and_then(|t| t.payor_id.clone()),
+            payee_id: transaction.and_then(|t| t.payee_id.clone()),
+            address_id,
+            payment_id: transaction.and_then(|t| t.method_id.clone()),
+            error: None,
+            method_error: None,
+        }
+    }
+
+    pub fn paid(transaction: &Transaction, address_id: Option<u64>) -> Self {
+        Self::of(TransactionRow::PAID, Some(transaction), address_id)
+    }
+
+    pub fn held(transaction: &Transaction, address_id: Option<u64>, reason: String) -> Self {
+        Self {
+            error: Some(reason),
+            ..Self::of(TransactionRow::HELD, Some(transaction), address_id)
+        }
+    }
+
+    pub fn dismissed(transaction: &Transaction, reason: String) -> Self {
+        Self {
+            error: Some(reason),
+            ..Self::of(TransactionRow::DISMISSED, Some(transaction), None)
+        }
+    }
+
+    /// `transaction` holds whatever ids were resolved before the row failed
+    pub fn failed(
+        transaction: Option<&Transaction>,
+        address_id: Option<u64>,
+        error: String,
+        method_error: Option<String>,
+    ) -> Self {
+        Self {
+            error: Some(error),
+            method_error,
+            ..Self::of(TransactionRow::FAILED, transaction, address_id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction() -> Transaction {
+        Transaction {
+            method_id: Some(String::from("pmt_paid")),
+            employee_id: Some(String::from("entity_employee")),
+            payee_id: Some(String::from("acc_payee")),
+            payor_id: Some(String::from("acc_payor")),
+            xml_id: Some(12),
+            row_index: Some(3),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rows_are_parsed_unless_they_failed_to() {
+        let row = TransactionRow::new(12, 3, String::from("{}"), None);
+        assert_eq!(row.status, TransactionRow::PARSED);
+        assert_eq!(row.error, None);
+
+        let row = TransactionRow::new(12, 4, String::from("{}"), Some(String::from("Bad amount")));
+        assert_eq!(row.status, TransactionRow::INVALID);
+        assert_eq!(row.error.as_deref(), Some("Bad amount"));
+        assert_eq!(row.row_index, 4);
+        assert_eq!(row.payment_id, None);
+    }
+
+    #[test]
+    fn paid_rows_keep_the_method_ids() {
+        let result = RowResult::paid(&transaction(), Some(9));
+        assert_eq!(result.status, TransactionRow::PAID);
+        assert_eq!(result.employee_id.as_deref(), Some("entity_employee"));
+        assert_eq!(result.payor_id.as_deref(), Some("acc_payor"));
+        assert_eq!(result.payee_id.as_deref(), Some("acc_payee"));
+        assert_eq!(result.address_id, Some(9));
+        assert_eq!(result.payment_id.as_deref(), Some("pmt_paid"));
+        assert_eq!(result.error, None);
+    }
+
+    #[test]
+    fn held_and_dismissed_rows_keep_the_reason() {
+        let result = RowResult::held(&transaction(), None, String::from("Duplicate of row 1"));
+        assert_eq!(result.status, TransactionRow::HELD);
+        assert_eq!(result.error.as_deref(), Some("Duplicate of row 1"));
+
+        let result = RowResult::dismissed(&transaction(), String::from("Duplicate of row 1"));
+        assert_eq!(result.status, TransactionRow::DISMISSED);
+        assert_eq!(result.error.as_deref(), Some("Duplicate of row 1"));
+        assert_eq!(result.address_id, None);
+    }
+
+    #[test]
+    fn failed_rows_keep_what_was_resolved_and_the_method_error() {
+        let partial = Transaction {
+            employee_id: Some(String::from("entity_employee")),
+            ..Default::default()
+        };
+        let result = RowResult::failed(
+            Some(&partial),
+            None,
+            String::from("Payor was rejected"),
+            Some(String::from("Invalid routing number")),
+        );
+        assert_eq!(result.status, TransactionRow::FAILED);
+        assert_eq!(result.employee_id.as_deref(), Some("entity_employee"));
+        assert_eq!(result.payor_id, None);
+        assert_eq!(result.payment_id, None);
+        assert_eq!(result.error.as_deref(), Some("Payor was rejected"));
+        assert_eq!(
+            result.method_error.as_deref(),
+            Some("Invalid routing number")
+        );
+
+        let result = RowResult::failed(None, None, String::from("Timed out"), None);
+        assert_eq!(result.employee_id, None);
+        assert_eq!(result.method_error, None);
+    }
+}
@@ -1,30 +1,205 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::error::BoxDynError;
+use sqlx::mysql::{MySqlTypeInfo, MySqlValueRef};
+use sqlx::{Decode, FromRow, MySql, Type};
+use std::fmt;
+use std::str::FromStr;
 use std::time::SystemTime;
 
+/// Where an import is in its life. Only the moves allowed by [XmlStatus::can_become] are ever
+/// written, the last four are final
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum XmlStatus {
+    Queued,
+    Processing,
+    Finished,
+    PartiallyFailed, // Some rows were paid or held and some failed
+    Failed,
+    Cancelled,
+}
+
+impl XmlStatus {
+    pub const ALL: [XmlStatus; 6] = [
+        XmlStatus::Queued,
+        XmlStatus::Processing,
+        XmlStatus::Finished,
+        XmlStatus::PartiallyFailed,
+        XmlStatus::Failed,
+        XmlStatus::Cancelled,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            XmlStatus::Queued => "Queued",
+            XmlStatus::Processing => "Processing",
+            XmlStatus::Finished => "Finished",
+            XmlStatus::PartiallyFailed => "PartiallyFailed",
+            XmlStatus::Failed => "Failed",
+            XmlStatus::Cancelled => "Cancelled",
+        }
+    }
+
+    pub fn is_final(self) -> bool {
+        !matches!(self, XmlStatus::Queued | XmlStatus::Processing)
+    }
+
+    /// Processing can start again when a worker resumes an import, or go back to Queued when an
+    /// attempt fails and will be retried
+    pub fn can_become(self, next: XmlStatus) -> bool {
+        match self {
+            XmlStatus::Queued => matches!(
+                next,
+                XmlStatus::Processing | XmlStatus::Failed | XmlStatus::Cancelled
+            ),
+            XmlStatus::Processing => true,
+            _ => false,
+        }
+    }
+
+    /// Every status `next` can be reached from
+    pub fn sources(next: XmlStatus) -> Vec<XmlStatus> {
+        Self::ALL
+            .into_iter()
+            .filter(|status| status.can_become(next))
+            .collect()
+    }
+}
+
+impl fmt::Display for XmlStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for XmlStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            // Imports recorded before statuses were typed started as Init
+            "Queued" | "Init" => Ok(XmlStatus::Queued),
+            "Processing" => Ok(XmlStatus::Processing),
+            "Finished" => Ok(XmlStatus::Finished),
+            "PartiallyFailed" => Ok(XmlStatus::PartiallyFailed),
+            "Failed" => Ok(XmlStatus::Failed),
+            "Cancelled" => Ok(XmlStatus::Cancelled),
+            other => Err(format!("Unknown xml status '{}'", other)),
+        }
+    }
+}
+
+// Stored as its name in a VARCHAR column
+impl Type<MySql> for XmlStatus {
+    fn type_info() -> MySqlTypeInfo {
+        <str as Type<MySql>>::type_info()
+    }
+
+    fn compatible(ty: &MySqlTypeInfo) -> bool {
+        <str as Type<MySql>>::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, MySql> for XmlStatus {
+    fn decode(value: MySqlValueRef<'r>) -> Result<Self, BoxDynError> {
+        let status = <&str as Decode<MySql>>::decode(value)?;
+        Ok(status.parse()?)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
 #[sqlx(rename_all = "PascalCase")]
 pub struct XmlParse {
     pub id: Option<u64>,
     pub filename: String,
-    pub status: String,
+    pub status: XmlStatus,
     pub started_at: String,
     pub finished_at: Option<String>,
+    pub content_hash: Option<String>, // SHA-256 of the uploaded file
+    pub duplicate_of: Option<u64>,    // Earlier import with the same content, when overridden
+    pub override_reason: Option<String>,
+    pub format: Option<String>, // How the upload is read, see FileFormat::name
+    pub profile: Option<String>, // Field mapping profile, the default layout if unset
+    pub checkpoint: Option<u64>, // Every row up to this one has been processed
 }
 
 impl XmlParse {
     pub const COLUMN_NAME: &'static str = "XmlParse";
 
-    pub fn new(filename: String, status: String) -> Self {
+    pub fn new(filename: String) -> Self {
         let time: DateTime<Utc> = SystemTime::now().into();
 
         Self {
             id: None,
             filename,
-            status,
+            status: XmlStatus::Queued,
             started_at: time.format("%d/%m/%Y %T").to_string(),
             finished_at: None,
+            content_hash: None,
+            duplicate_of: None,
+            override_reason: None,
+            format: None,
+            profile: None,
+            checkpoint: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queued_imports_start_fail_or_are_cancelled() {
+        assert!(XmlStatus::Queued.can_become(XmlStatus::Processing));
+        assert!(XmlStatus::Queued.can_become(XmlStatus::Failed));
+        assert!(XmlStatus::Queued.can_become(XmlStatus::Cancelled));
+        assert!(!XmlStatus::Queued.can_become(XmlStatus::Queued));
+        assert!(!XmlStatus::Queued.can_become(XmlStatus::Finished));
+        assert!(!XmlStatus::Queued.can_become(XmlStatus::PartiallyFailed));
+    }
+
+    #[test]
+    fn processing_imports_can_become_anything() {
+        for next in XmlStatus::ALL {
+            assert!(XmlStatus::Processing.can_become(next), "{}", next);
+        }
+    }
+
+    #[test]
+    fn final_statuses_never_change() {
+        for status in XmlStatus::ALL
+            .into_iter()
+            .filter(|status| status.is_final())
+        {
+            for next in XmlStatus::ALL {
+                assert!(!status.can_become(next), "{} -> {}", status, next);
+            }
+        }
+    }
+
+    #[test]
+    fn sources_match_can_become() {
+        assert_eq!(
+            XmlStatus::sources(XmlStatus::Processing),
+            vec![XmlStatus::Queued, XmlStatus::Processing]
+        );
+        assert_eq!(
+            XmlStatus::sources(XmlStatus::Finished),
+            vec![XmlStatus::Processing]
+        );
+        assert_eq!(
+            XmlStatus::sources(XmlStatus::Queued),
+            vec![XmlStatus::Processing]
+        );
+    }
+
+    #[test]
+    fn statuses_round_trip_through_their_names() {
+        for status in XmlStatus::ALL {
+            assert_eq!(status.as_str().parse::<XmlStatus>(), Ok(status));
         }
+        assert_eq!("Init".parse::<XmlStatus>(), Ok(XmlStatus::Queued));
+        assert!("Done".parse::<XmlStatus>().is_err());
     }
 }
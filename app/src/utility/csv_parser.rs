@@ -0,0 +1,366 @@
+use csv::{ReaderBuilder, StringRecord, StringRecordsIntoIter};
+use log::{error, info, warn};
+use std::io::Read;
+use xml::common::TextPosition;
+
+use crate::schema::address::Address;
+use crate::schema::employee::Employee;
+use crate::schema::payee::Payee;
+use crate::schema::payor::Payor;
+use crate::schema::transaction::Transaction;
+use crate::utility::parser::{
+    set_address_field, set_employee_field, set_payee_field, set_payor_field, set_transaction_field,
+    validate_row, ParseError, ParsedRow, RawValues, RowFailure,
+};
+
+/// Which entity a column feeds, along with the lowercased field name within it
+#[derive(Debug, Clone)]
+enum Column {
+    Employee(String),
+    Payor(String),
+    Address(String),
+    Payee(String),
+    Transaction(String),
+    Unknown,
+}
+
+impl Column {
+    /// Headers are matched ignoring case and punctuation, so `Employee.DunkinId`,
+    /// `employee_dunkin_id` and `EmployeeDunkinId` are the same column
+    fn from_header(header: &str) -> Self {
+        let normalized: String = header
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_lowercase();
+
+        // Address comes first so `PayorAddressZip` is not read as a payor field
+        let address = normalized
+            .strip_prefix("payoraddress")
+            .or_else(|| normalized.strip_prefix("address"));
+        if let Some(field) = address {
+            return Column::Address(field.to_string());
+        }
+        if let Some(field) = normalized.strip_prefix("employee") {
+            return Column::Employee(field.to_string());
+        }
+        if let Some(field) = normalized.strip_prefix("payor") {
+            return Column::Payor(field.to_string());
+        }
+        if let Some(field) = normalized.strip_prefix("payee") {
+            return Column::Payee(field.to_string());
+        }
+
+        match normalized.as_str() {
+            "amount" => Column::Transaction(normalized),
+            _ => Column::Unknown,
+        }
+    }
+}
+
+/// Reads payroll rows from a CSV export with one column per field, e.g. `Employee.DunkinId`,
+/// `Payor.Address.Zip` and `Amount`. Yields the same rows as [crate::utility::parser::RowReader]
+pub struct CsvRowReader<R: Read> {
+    headers: Vec<String>,
+    columns: Vec<Column>,
+    records: StringRecordsIntoIter<R>,
+    row_index: u64,
+    finished: bool,
+    header_failure: Option<RowFailure>,
+}
+
+impl<R: Read> CsvRowReader<R> {
+    pub fn new(reader: R) -> Self {
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let (headers, header_failure) = match reader.headers() {
+            Ok(headers) => (headers.iter().map(str::to_string).collect(), None),
+            Err(e) => {
+                error!("Failed to read csv headers due to {}", e);
+                let failure = RowFailure {
+                    row_index: 0,
+                    position: TextPosition::new(),
+                    raw: RawValues::new(),
+                    error: ParseError::Malformed {
+                        reason: e.to_string(),
+                        position: TextPosition::new(),
+                    },
+                };
+                (vec![], Some(failure))
+            }
+        };
+
+        let columns: Vec<Column> = headers
+            .iter()
+            .map(|header: &String| {
+                let column = Column::from_header(header);
+                if let Column::Unknown = column {
+                    warn!("Csv: Failed to match column '{}', ignoring it", header);
+                }
+                column
+            })
+            .collect();
+
+        Self {
+            headers,
+            columns,
+            records: reader.into_records(),
+            row_index: 0,
+            finished: false,
+            header_failure,
+        }
+    }
+
+    /// Each non-empty cell keyed by its header, as it was before any parsing
+    fn raw_values(&self, record: &StringRecord) -> RawValues {
+        self.headers
+            .iter()
+            .zip(record.iter())
+            .filter(|(_, text)| !text.is_empty())
+            .map(|(header, text)| (header.clone(), text.to_string()))
+            .collect()
+    }
+
+    fn parse_record(
+        &self,
+        record: &StringRecord,
+        position: TextPosition,
+    ) -> Result<ParsedRow, ParseError> {
+        let mut employee = Employee::new();
+        let mut payor = Payor::new();
+        let mut address: Option<Address> = None;
+        let mut payee = Payee::new();
+        let mut transaction = Transaction::new();
+
+        for (index, text) in record.iter().enumerate() {
+            // Empty cells are left unset so they are reported as missing rather than invalid
+            if text.is_empty() {
+                continue;
+            }
+            let updated = match self.columns.get(index).unwrap_or(&Column::Unknown) {
+                Column::Employee(field) => set_employee_field(&mut employee, field, text),
+                Column::Payor(field) => set_payor_field(&mut payor, field, text),
+                Column::Address(field) => {
+                    set_address_field(address.get_or_insert_with(Address::new), field, text)
+                }
+                Column::Payee(field) => set_payee_field(&mut payee, field, text),
+                Column::Transaction(field) => set_transaction_field(&mut transaction, field, text),
+                Column::Unknown => Ok(true),
+            };
+
+            let header = self.headers.get(index).map(String::as_str).unwrap_or("");
+            match updated {
+                Ok(true) => {}
+                Ok(false) => {
+                    warn!(
+                        "Csv: Column '{}' does not match a field, ignoring it",
+                        header
+                    );
+                }
+                Err(reason) => {
+                    return Err(ParseError::invalid_value(
+                        header,
+                        text,
+                        reason,
+                        TextPosition {
+                            row: position.row,
+                            column: index as u64,
+                        },
+                    ));
+                }
+            }
+        }
+
+        if transaction.amount.is_none() {
+            return Err(ParseError::missing("Amount", position));
+        }
+
+        Ok(ParsedRow {
+            row_index: self.row_index,
+            position,
+            raw: RawValues::new(), // Filled in by next, which keeps them for failures too
+            employee,
+            payor,
+            address,
+            payee,
+            transaction,
+        })
+    }
+}
+
+impl<R: Read> Iterator for CsvRowReader<R> {
+    type Item = Result<ParsedRow, RowFailure>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        if let Some(failure) = self.header_failure.take() {
+            self.finished = true;
+            return Some(Err(failure));
+        }
+
+        let record = match self.records.next() {
+            Some(record) => record,
+            None => {
+                info!("End of document");
+                self.finished = true;
+                return None;
+            }
+        };
+        self.row_index += 1;
+
+        // Positions are 0-based like the XML reader's, with the column being the field index
+        let line = match &record {
+            Ok(record) => record.position().map(|p| p.line()),
+            Err(e) => e.position().map(|p| p.line()),
+        };
+        let position = TextPosition {
+            row: line.unwrap_or(self.row_index + 1).saturating_sub(1),
+            column: 0,
+        };
+
+        let raw = match &record {
+            Ok(record) => self.raw_values(record),
+            Err(_) => RawValues::new(),
+        };
+        let result = match record {
+            Ok(record) => self.parse_record(&record, position).and_then(|row| {
+                validate_row(&row)?;
+                Ok(row)
+            }),
+            Err(e) => {
+                error!("Error parsing csv record due to {}", e);
+                // An io error leaves the reader somewhere unknown, anything else is just this row
+                if let csv::ErrorKind::Io(_) = e.kind() {
+                    self.finished = true;
+                }
+                Err(ParseError::Malformed {
+                    reason: e.to_string(),
+                    position,
+                })
+            }
+        };
+
+        Some(match result {
+            Ok(row) => Ok(ParsedRow { raw, ..row }),
+            Err(error) => Err(RowFailure {
+                row_index: self.row_index,
+                position,
+                raw,
+                error,
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str =
+        "Employee.DunkinId,employee_dunkin_branch,EmployeeFirstName,Employee.LastName,\
+        Payor.DunkinId,Payor.Address.Zip,Payee.PlaidId,Payee.LoanAccountNumber,Amount,Notes";
+
+    fn rows(body: &str) -> Vec<Result<ParsedRow, RowFailure>> {
+        CsvRowReader::new(format!("{}\n{}", HEADER, body).as_bytes()).collect()
+    }
+
+    #[test]
+    fn matches_headers_ignoring_case_and_punctuation() {
+        assert!(matches!(
+            Column::from_header("Employee.DunkinId"),
+            Column::Employee(field) if field == "dunkinid"
+        ));
+        assert!(matches!(
+            Column::from_header("employee_dunkin_id"),
+            Column::Employee(field) if field == "dunkinid"
+        ));
+        assert!(matches!(
+            Column::from_header("Payor.Address.Zip"),
+            Column::Address(field) if field == "zip"
+        ));
+        assert!(matches!(
+            Column::from_header("PayorName"),
+            Column::Payor(field) if field == "name"
+        ));
+        assert!(matches!(
+            Column::from_header("AMOUNT"),
+            Column::Transaction(field) if field == "amount"
+        ));
+        assert!(matches!(Column::from_header("Notes"), Column::Unknown));
+    }
+
+    #[test]
+    fn reads_every_field_of_a_row() {
+        let rows =
+            rows("EMP1, Branch 1,Ada,Lovelace,PAY1,02134,plaid-1,12345,\"$1,234.50\",ignored");
+        assert_eq!(rows.len(), 1);
+
+        let row = rows[0].as_ref().unwrap();
+        assert_eq!(row.row_index, 1);
+        assert_eq!(row.position.row, 1);
+        assert_eq!(row.employee.dunkin_id.as_deref(), Some("EMP1"));
+        assert_eq!(row.employee.dunkin_branch.as_deref(), Some("Branch 1"));
+        assert_eq!(row.payor.dunkin_id.as_deref(), Some("PAY1"));
+        assert_eq!(
+            row.address.as_ref().and_then(|address| address.zip),
+            Some(2134)
+        );
+        assert_eq!(row.payee.loan_account_number, Some(12345));
+        assert_eq!(
+            row.transaction.amount.map(|amount| amount.cents()),
+            Some(123_450)
+        );
+        assert_eq!(row.raw.len(), 10);
+    }
+
+    #[test]
+    fn fails_only_the_rows_that_are_wrong() {
+        let rows = rows(
+            "EMP1,B1,Ada,Lovelace,PAY1,02134,plaid-1,12345,8.15,\n\
+             EMP2,B1,Alan,Turing,PAY1,02134,plaid-2,not-a-number,8.15,\n\
+             EMP3,B1,Grace,Hopper,PAY1,02134,plaid-3,12347,,\n\
+             EMP4,B1,Edsger,,PAY1,02134,plaid-4,12348,8.15,\n\
+             EMP5,B1,Barbara,Liskov,PAY1,02134,plaid-5,12349,9.00,",
+        );
+        assert_eq!(rows.len(), 5);
+        assert!(rows[0].is_ok());
+        assert!(rows[4].is_ok());
+
+        let failure = rows[1].as_ref().unwrap_err();
+        assert_eq!(failure.row_index, 2);
+        assert!(matches!(
+            &failure.error,
+            ParseError::InvalidValue { element, value, position, .. }
+                if element == "Payee.LoanAccountNumber" && value == "not-a-number" && position.column == 7
+        ));
+        // Kept so the report can show what the row held
+        assert_eq!(failure.raw.len(), 9);
+
+        assert!(matches!(
+            &rows[2].as_ref().unwrap_err().error,
+            ParseError::MissingElement { element, .. } if element == "Amount"
+        ));
+        assert!(matches!(
+            &rows[3].as_ref().unwrap_err().error,
+            ParseError::MissingElement { element, .. } if element.ends_with("LastName")
+        ));
+    }
+
+    #[test]
+    fn reports_rows_with_the_wrong_number_of_cells() {
+        let rows = rows("EMP1,B1,Ada");
+        assert_eq!(rows.len(), 1);
+        assert!(matches!(
+            rows[0].as_ref().unwrap_err().error,
+            ParseError::Malformed { .. }
+        ));
+    }
+
+    #[test]
+    fn reads_nothing_from_an_empty_file() {
+        assert_eq!(rows("").len(), 0);
+    }
+}
@@ -0,0 +1,255 @@
+use sqlx::{MySql, Pool};
+use std::collections::HashMap;
+
+use crate::schema::money::Money;
+use crate::schema::transaction::Transaction;
+use crate::utility::env::parse_or;
+
+/// What payments are compared on, two payments with the same key are likely duplicates
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PaymentKey {
+    pub employee_id: String,
+    pub payee_id: String,
+    pub amount: Money,
+}
+
+impl PaymentKey {
+    pub fn of(transaction: &Transaction) -> Option<Self> {
+        Some(Self {
+            employee_id: transaction.employee_id.clone()?,
+            payee_id: transaction.payee_id.clone()?,
+            amount: transaction.amount?,
+        })
+    }
+}
+
+/// The payments a new one is compared against, both from earlier in the file being imported
+/// and from recent imports
+#[derive(Debug, Default)]
+pub struct PaymentHistory {
+    in_file: HashMap<PaymentKey, u64>,     // First row with each key
+    recent: HashMap<PaymentKey, Vec<u64>>, // Xml ids of other imports with each key
+}
+
+impl PaymentHistory {
+    /// Loads payments to `employee_ids` made by imports other than `xml_id` in the last `days`
+    pub async fn load(
+        pool: &Pool<MySql>,
+        xml_id: u64,
+        employee_ids: &[String],
+        days: u64,
+    ) -> Result<Self, sqlx::Error> {
+        let mut history = Self::default();
+        if days == 0 {
+            return Ok(history);
+        }
+
+        for transaction in
+            Transaction::get_recent_by_employees(pool, employee_ids, xml_id, days).await?
+        {
+            if let (Some(key), Some(xml_id)) = (PaymentKey::of(&transaction), transaction.xml_id) {
+                history.recent.entry(key).or_default().push(xml_id);
+            }
+        }
+        Ok(history)
+    }
+
+    /// Remembers a row of the file that was paid or held, so later rows are compared against it
+    pub fn remember(&mut self, key: PaymentKey, row_index: u64) {
+        self.in_file.entry(key).or_insert(row_index);
+    }
+}
+
+/// A check for likely duplicate payments, gives the reason when `key` looks like one
+pub trait DuplicateRule: Send + Sync {
+    fn check(&self, key: &PaymentKey, history: &PaymentHistory) -> Option<String>;
+}
+
+/// The same employee, payee and amount earlier in the same file
+pub struct SameFile;
+
+impl DuplicateRule for SameFile {
+    fn check(&self, key: &PaymentKey, history: &PaymentHistory) -> Option<String> {
+        history.in_file.get(key).map(|row_index| {
+            format!(
+                "Same employee, payee and amount of {} as row {}",
+                key.amount, row_index
+            )
+        })
+    }
+}
+
+/// The same employee, payee and amount paid by another import in the last `days` days
+pub struct RecentlyPaid {
+    pub days: u64,
+}
+
+impl DuplicateRule for RecentlyPaid {
+    fn check(&self, key: &PaymentKey, history: &PaymentHistory) -> Option<String> {
+        history.recent.get(key).map(|xml_ids| {
+            let xml_ids: Vec<String> = xml_ids.iter().map(u64::to_string).collect();
+            format!(
+                "Same employee, payee and amount of {} was paid by xml {} in the last {} days",
+                key.amount,
+                xml_ids.join(", "),
+                self.days
+            )
+        })
+    }
+}
+
+pub struct DuplicateRules {
+    rules: Vec<Box<dyn DuplicateRule>>,
+    window_days: u64,
+}
+
+impl DuplicateRules {
+    /// Every rule, with imports compared against the last `DUPLICATE_WINDOW_DAYS` days
+    pub fn from_env() -> Self {
+        Self::new(get_duplicate_window_days())
+    }
+
+    /// Every rule, with imports compared against the last `window_days` days, none if 0
+    pub fn new(window_days: u64) -> Self {
+        let mut rules: Vec<Box<dyn DuplicateRule>> = vec![Box::new(SameFile)];
+        if window_days > 0 {
+            rules.push(Box::new(RecentlyPaid { days: window_days }));
+        }
+        Self { rules, window_days }
+    }
+
+    pub fn window_days(&self) -> u64 {
+        self.window_days
+    }
+
+    /// Runs every rule against `key`. Rows with the same key must be checked in file order, each
+    /// after the one before it was remembered, so the first of a set is the one paid
+    pub fn check(&self, key: &PaymentKey, history: &PaymentHistory) -> Option<String> {
+        let reasons: Vec<String> = self
+            .rules
+            .iter()
+            .filter_map(|rule| rule.check(key, history))
+            .collect();
+
+        match reasons.is_empty() {
+            true => None,
+            false => Some(reasons.join("; ")),
+        }
+    }
+}
+
+fn get_duplicate_window_days() -> u64 {
+    parse_or("DUPLICATE_WINDOW_DAYS", 7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(employee_id: &str, cents: u64) -> PaymentKey {
+        PaymentKey {
+            employee_id: employee_id.to_string(),
+            payee_id: format!("payee-{}", employee_id),
+            amount: Money::from_cents(cents),
+        }
+    }
+
+    #[test]
+    fn keys_need_employee_payee_and_amount() {
+        let mut transaction = Transaction {
+            employee_id: Some(String::from("emp")),
+            payee_id: Some(String::from("payee-emp")),
+            amount: Some(Money::from_cents(815)),
+            ..Default::default()
+        };
+        assert_eq!(PaymentKey::of(&transaction), Some(key("emp", 815)));
+
+        transaction.payee_id = None;
+        assert_eq!(PaymentKey::of(&transaction), None);
+    }
+
+    /// Checks each row in turn, remembering it if it wasn't held, as if every row was paid
+    fn check_all(rules: &DuplicateRules, rows: &[PaymentKey]) -> Vec<Option<String>> {
+        let mut history = PaymentHistory::default();
+        rows.iter()
+            .enumerate()
+            .map(|(i, key)| {
+                let reason = rules.check(key, &history);
+                history.remember(key.clone(), i as u64 + 1);
+                reason
+            })
+            .collect()
+    }
+
+    #[test]
+    fn holds_repeats_within_a_file_but_not_the_first() {
+        let rules = DuplicateRules::new(0);
+        let reasons = check_all(
+            &rules,
+            &[
+                key("emp", 815),
+                key("emp", 900),
+                key("other", 815),
+                key("emp", 815),
+                key("emp", 815),
+            ],
+        );
+        let repeat = Some(String::from(
+            "Same employee, payee and amount of $8.15 as row 1",
+        ));
+        // Still compared to the first row, not the last repeat
+        assert_eq!(reasons, vec![None, None, None, repeat.clone(), repeat]);
+    }
+
+    #[test]
+    fn only_remembered_rows_are_held_against() {
+        let rules = DuplicateRules::new(0);
+        let mut history = PaymentHistory::default();
+
+        // Row 1 failed to pay, so row 2 is the first of the set
+        assert_eq!(rules.check(&key("emp", 815), &history), None);
+        assert_eq!(rules.check(&key("emp", 815), &history), None);
+        history.remember(key("emp", 815), 2);
+        assert_eq!(
+            rules.check(&key("emp", 815), &history),
+            Some(String::from(
+                "Same employee, payee and amount of $8.15 as row 2"
+            ))
+        );
+    }
+
+    #[test]
+    fn holds_payments_made_by_recent_imports() {
+        let rules = DuplicateRules::new(7);
+        let mut history = PaymentHistory::default();
+        history.recent.insert(key("emp", 815), vec![3, 4]);
+
+        assert_eq!(
+            rules.check(&key("emp", 815), &history),
+            Some(String::from(
+                "Same employee, payee and amount of $8.15 was paid by xml 3, 4 in the last 7 days"
+            ))
+        );
+        assert_eq!(rules.check(&key("emp", 900), &history), None);
+    }
+
+    #[test]
+    fn gives_every_reason_a_payment_was_held() {
+        let rules = DuplicateRules::new(7);
+        let mut history = PaymentHistory::default();
+        history.recent.insert(key("emp", 815), vec![3]);
+        history.remember(key("emp", 815), 1);
+
+        let reason = rules.check(&key("emp", 815), &history).unwrap();
+        assert_eq!(reason.split("; ").count(), 2, "{}", reason);
+    }
+
+    #[test]
+    fn a_zero_window_ignores_recent_imports() {
+        let rules = DuplicateRules::new(0);
+        let mut history = PaymentHistory::default();
+        history.recent.insert(key("emp", 815), vec![3]);
+
+        assert_eq!(rules.check(&key("emp", 815), &history), None);
+    }
+}
@@ -0,0 +1,78 @@
+use log::warn;
+use std::env;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// The environment variable `name` parsed as a `T`, or `default` if it isn't set or can't be
+/// parsed
+pub fn parse_or<T>(name: &str, default: T) -> T
+where
+    T: FromStr + Display,
+    T::Err: Display,
+{
+    match env::var(name) {
+        Ok(value) => match value.parse::<T>() {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                warn!("Environment Variable {} with value {} could not be parsed due to '{}', defaulting to {}", name, value, e, default);
+                default
+            }
+        },
+        Err(_) => {
+            warn!(
+                "Environment Variable {} not set, defaulting to {}",
+                name, default
+            );
+            default
+        }
+    }
+}
+
+/// Like [parse_or], also defaulting if the value isn't above zero
+pub fn positive_or<T>(name: &str, default: T) -> T
+where
+    T: FromStr + Display + Default + PartialOrd + Copy,
+    T::Err: Display,
+{
+    let value = parse_or(name, default);
+    if value > T::default() {
+        return value;
+    }
+    warn!(
+        "Environment Variable {} with value {} is not positive, defaulting to {}",
+        name, value, default
+    );
+    default
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each test has its own variables, as tests run in parallel and share the environment
+
+    #[test]
+    fn defaults_when_unset() {
+        env::remove_var("ENV_TEST_UNSET");
+        assert_eq!(parse_or("ENV_TEST_UNSET", 7u64), 7);
+    }
+
+    #[test]
+    fn parses_set_values() {
+        env::set_var("ENV_TEST_SET", "12");
+        assert_eq!(parse_or("ENV_TEST_SET", 7u64), 12);
+    }
+
+    #[test]
+    fn defaults_when_unparsable() {
+        env::set_var("ENV_TEST_UNPARSABLE", "twelve");
+        assert_eq!(parse_or("ENV_TEST_UNPARSABLE", 7u64), 7);
+    }
+
+    #[test]
+    fn positive_rejects_zero() {
+        env::set_var("ENV_TEST_ZERO", "0");
+        assert_eq!(parse_or("ENV_TEST_ZERO", 7u64), 0);
+        assert_eq!(positive_or("ENV_TEST_ZERO", 7u64), 7);
+    }
+}
@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use crate::schema::address::Address;
+use crate::schema::employee::Employee;
+use crate::schema::payee::Payee;
+use crate::schema::payor::Payor;
+use crate::schema::transaction::Transaction;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid profile name '{0}'")]
+    InvalidName(String),
+    #[error("Profile '{0}' could not be read: {1}")]
+    IOError(String, #[source] std::io::Error),
+    #[error("Profile '{0}' is not valid: {1}")]
+    ParseError(String, #[source] serde_json::Error),
+}
+
+/// Renames of the child elements within each wrapper, from the lowercased element name in the
+/// file to the field it feeds. Anything not listed is matched by its own name
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct FieldRenames {
+    pub row: HashMap<String, String>,
+    pub employee: HashMap<String, String>,
+    pub payor: HashMap<String, String>,
+    pub address: HashMap<String, String>,
+    pub payee: HashMap<String, String>,
+}
+
+/// Describes an employer's XML export, which element wraps each record and entity and which
+/// elements feed which fields. The default is the Dunkin layout of `<row><Employee>...`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FieldMapping {
+    pub row: String,
+    pub employee: String,
+    pub payor: String,
+    pub address: String,
+    pub payee: String,
+    pub fields: FieldRenames,
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        Self {
+            row: Transaction::XML_IDENTIFIER.to_string(),
+            employee: Employee::XML_IDENTIFIER.to_string(),
+            payor: Payor::XML_IDENTIFIER.to_string(),
+            address: Address::XML_IDENTIFIER.to_string(),
+            payee: Payee::XML_IDENTIFIER.to_string(),
+            fields: FieldRenames::default(),
+        }
+    }
+}
+
+impl FieldMapping {
+    /// Loads `{MAPPING_PROFILE_DIR}/{name}.json`, or the default layout when no name is given
+    pub fn load(profile: Option<&str>) -> Result<Self, Error> {
+        let name = match profile {
+            Some(name) => name,
+            None => return Ok(Self::default()),
+        };
+        // Names end up in a path, so nothing that could leave the profile directory is allowed
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(Error::InvalidName(name.to_string()));
+        }
+
+        let path = profile_dir().join(format!("{}.json", name));
+        let file = File::open(path).map_err(|e| Error::IOError(name.to_string(), e))?;
+        let mut mapping: Self = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| Error::ParseError(name.to_string(), e))?;
+
+        // Child elements are matched ignoring case, like they always have been
+        for renames in [
+            &mut mapping.fields.row,
+            &mut mapping.fields.employee,
+            &mut mapping.fields.payor,
+            &mut mapping.fields.address,
+            &mut mapping.fields.payee,
+        ] {
+            *renames = renames
+                .drain()
+                .map(|(element, field)| (element.to_lowercase(), field.to_lowercase()))
+                .collect();
+        }
+        Ok(mapping)
+    }
+
+    pub fn row_field<'a>(&'a self, element: &'a str) -> &'a str {
+        Self::rename(&self.fields.row, element)
+    }
+
+    pub fn employee_field<'a>(&'a self, element: &'a str) -> &'a str {
+        Self::rename(&self.fields.employee, element)
+    }
+
+    pub fn payor_field<'a>(&'a self, element: &'a str) -> &'a str {
+        Self::rename(&self.fields.payor, element)
+    }
+
+    pub fn address_field<'a>(&'a self, element: &'a str) -> &'a str {
+        Self::rename(&self.fields.address, element)
+    }
+
+    pub fn payee_field<'a>(&'a self, element: &'a str) -> &'a str {
+        Self::rename(&self.fields.payee, element)
+    }
+
+    fn rename<'a>(renames: &'a HashMap<String, String>, element: &'a str) -> &'a str {
+        renames.get(element).map(String::as_str).unwrap_or(element)
+    }
+}
+
+fn profile_dir() -> PathBuf {
+    match env::var("MAPPING_PROFILE_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from("mappings"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_profile_is_the_default_layout() {
+        let mapping = FieldMapping::load(None).unwrap();
+        assert_eq!(mapping.row, Transaction::XML_IDENTIFIER);
+        assert_eq!(mapping.employee, Employee::XML_IDENTIFIER);
+        assert_eq!(mapping.employee_field("dunkinid"), "dunkinid");
+    }
+
+    #[test]
+    fn rejects_names_that_could_leave_the_profile_directory() {
+        for name in ["", "../example", "/etc/passwd", "example.json", "a b"] {
+            assert!(
+                matches!(FieldMapping::load(Some(name)), Err(Error::InvalidName(_))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn loads_profiles_with_renames_ignoring_case() {
+        // Read from the mappings directory shipped next to Cargo.toml
+        let mapping = FieldMapping::load(Some("example")).unwrap();
+        assert_eq!(mapping.row, "Payment");
+        assert_eq!(mapping.employee, "Worker");
+        assert_eq!(mapping.row_field("netamount"), "amount");
+        assert_eq!(mapping.employee_field("workerid"), "dunkinid");
+        assert_eq!(mapping.employee_field("birthdate"), "dob");
+        assert_eq!(mapping.payor_field("routingnumber"), "abarouting");
+        assert_eq!(mapping.address_field("postalcode"), "zip");
+        assert_eq!(mapping.payee_field("loanaccount"), "loanaccountnumber");
+        // Anything not renamed keeps its own name
+        assert_eq!(mapping.employee_field("firstname"), "firstname");
+    }
+
+    #[test]
+    fn reports_missing_profiles() {
+        assert!(matches!(
+            FieldMapping::load(Some("no-such-profile")),
+            Err(Error::IOError(name, _)) if name == "no-such-profile"
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+use log::{debug, error, info, warn};
+use sqlx::{MySql, Pool};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+use tokio::task;
+
+use crate::schema::job::Job;
+use crate::schema::row_error::RowError;
+use crate::schema::xml_parse::{XmlParse, XmlStatus};
+use crate::schema::{SqlString, CRUD};
+use crate::utility::jobs;
+use crate::utility::parser::{record_row_error, FileFormat, RowFailure};
+use crate::utility::upload;
+use crate::utility::xsd::PAYROLL_SCHEMA;
+
+/// What was uploaded and how it should be imported
+#[derive(Debug, Clone)]
+pub struct ImportRequest {
+    pub filename: String,
+    pub format: FileFormat,
+    /// Field mapping profile the file was exported with, only applies to XML
+    pub profile: Option<String>,
+    /// Check XML against the published schema before it is queued
+    pub strict: bool,
+    /// Why an identical file is being imported again, without one duplicates are turned down
+    pub override_reason: Option<String>,
+    /// Queue the job already claimed by this process, which runs it with [jobs::run_now]
+    pub run_here: bool,
+}
+
+#[derive(Debug)]
+pub enum Started {
+    /// Recorded and queued, any job worker may run it unless it was queued to run here
+    Queued(Box<XmlParse>, Job),
+    /// The earliest import of the same content, the upload was not recorded
+    Duplicate(XmlParse),
+    /// Not queued, `xml_id` is set if the upload was recorded before it was turned down
+    Rejected {
+        xml_id: Option<u64>,
+        reason: String,
+        errors: Vec<RowError>,
+    },
+}
+
+/// Records a spooled upload, checks it against the schema if asked to, and queues it. Every way
+/// into an import goes through here, so they all treat duplicates and strict mode the same
+pub async fn start_import(
+    pool: &Pool<MySql>,
+    file: NamedTempFile,
+    spooled: upload::Spooled,
+    request: &ImportRequest,
+) -> Started {
+    let mut xml = XmlParse::new(request.filename.clone());
+    xml.format = Some(request.format.name().to_string());
+    xml.profile = request.profile.clone();
+    let override_reason = request.override_reason.as_deref();
+    let (mut xml, path) = match record_upload(pool, xml, file, spooled, override_reason).await {
+        Ok(Recorded::New(xml, path)) => (xml, path),
+        Ok(Recorded::Duplicate(earlier)) => return Started::Duplicate(earlier),
+        Err(reason) => {
+            return Started::Rejected {
+                xml_id: None,
+                reason,
+                errors: vec![],
+            }
+        }
+    };
+    let id = xml.id.expect("Id was set");
+
+    if request.strict && request.format == FileFormat::Xml {
+        let rejection = match check_schema(&path).await {
+            Ok(violations) if violations.is_empty() => None,
+            Ok(violations) => {
+                for violation in &violations {
+                    record_row_error(pool, id, violation).await;
+                }
+                Some((
+                    format!(
+                        "Failed schema validation with {} violation(s)",
+                        violations.len()
+                    ),
+                    violations
+                        .iter()
+                        .map(|violation| violation.to_row_error(Some(id)))
+                        .collect(),
+                ))
+            }
+            Err(e) => {
+                error!(
+                    "Failed to check {} against the schema due to {}",
+                    xml.filename, e
+                );
+                Some((e, vec![]))
+            }
+        };
+
+        if let Some((reason, errors)) = rejection {
+            fail(pool, &mut xml, &path).await;
+            return Started::Rejected {
+                xml_id: Some(id),
+                reason,
+                errors,
+            };
+        }
+    }
+
+    let enqueued = if request.run_here {
+        jobs::enqueue_import_here(pool, id).await
+    } else {
+        jobs::enqueue_import(pool, id).await
+    };
+    match enqueued {
+        Ok(job) => Started::Queued(Box::new(xml), job),
+        Err(e) => {
+            error!("Failed to enqueue import of xml {} due to {}", id, e);
+            fail(pool, &mut xml, &path).await;
+            Started::Rejected {
+                xml_id: Some(id),
+                reason: String::from("Failed to queue the import"),
+                errors: vec![],
+            }
+        }
+    }
+}
+
+enum Recorded {
+    New(XmlParse, PathBuf),
+    /// The earliest import of the same content, the upload was not recorded
+    Duplicate(XmlParse),
+}
+
+/// Records a spooled upload and moves it to its [upload::upload_path], unless the same content
+/// was imported before and no reason was given to import it again
+async fn record_upload(
+    pool: &Pool<MySql>,
+    mut xml: XmlParse,
+    file: NamedTempFile,
+    spooled: upload::Spooled,
+    override_reason: Option<&str>,
+) -> Result<Recorded, String> {
+    let override_reason = override_reason
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty());
+    let content_hash = spooled.content_hash;
+    xml.content_hash = Some(content_hash.clone());
+
+    // Inserted before looking for an earlier import, the unique key on ActiveContentHash turns
+    // the second of two identical uploads away even if they race each other
+    debug!(
+        "Creating {} entry for: {}",
+        XmlParse::COLUMN_NAME,
+        xml.filename
+    );
+    let id = match xml.insert(pool).await {
+        Ok(id) => id,
+        Err(e) if is_unique_violation(&e) => {
+            let earlier = match XmlParse::get_by_content_hash(pool, &content_hash).await {
+                Ok(Some(earlier)) => earlier,
+                Ok(None) => {
+                    error!(
+                        "Xml with hash {} failed while {} was being recorded",
+                        content_hash, xml.filename
+                    );
+                    return Err(String::from("Failed to record the upload"));
+                }
+                Err(e) => {
+                    error!("Failed to get xmls with hash {} due to {}", content_hash, e);
+                    return Err(String::from("Failed to check for an earlier upload"));
+                }
+            };
+            let Some(reason) = override_reason else {
+                return Ok(Recorded::Duplicate(earlier));
+            };
+
+            warn!(
+                "Importing {} again as a duplicate of xml {} because '{}'",
+                xml.filename,
+                SqlString::from(earlier.id),
+                reason
+            );
+            xml.duplicate_of = earlier.id;
+            xml.override_reason = Some(reason.to_string());
+            insert(pool, &xml).await?
+        }
+        Err(e) => {
+            error!("Failed to record upload of {} due to {}", xml.filename, e);
+            return Err(String::from("Failed to record the upload"));
+        }
+    };
+    xml.id = Some(id);
+    info!("Recorded upload of {} as xml {}", xml.filename, id);
+
+    match upload::keep(file, id) {
+        Ok(path) => Ok(Recorded::New(xml, path)),
+        Err(e) => {
+            error!("Failed to keep upload of xml {} due to {}", id, e);
+            if let Err(e) = xml.set_status(pool, XmlStatus::Failed).await {
+                error!("Failed to set xml with id {} as failed due to {}", id, e);
+            }
+            Err(e.to_string())
+        }
+    }
+}
+
+async fn insert(pool: &Pool<MySql>, xml: &XmlParse) -> Result<u64, String> {
+    xml.insert(pool).await.map_err(|e| {
+        error!("Failed to record upload of {} due to {}", xml.filename, e);
+        String::from("Failed to record the upload")
+    })
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .is_some_and(|e| e.is_unique_violation())
+}
+
+/// Checks an XML file against [PAYROLL_SCHEMA], returning every violation found
+pub async fn check_schema(path: &Path) -> Result<Vec<RowFailure>, String> {
+    let path = path.to_path_buf();
+    task::spawn_blocking(move || {
+        File::open(path)
+            .map(|file| PAYROLL_SCHEMA.check(file))
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Drops the upload of an import that won't be run
+async fn fail(pool: &Pool<MySql>, xml: &mut XmlParse, path: &Path) {
+    upload::remove(path).await;
+    if let Err(e) = xml.set_status(pool, XmlStatus::Failed).await {
+        error!(
+            "Failed to set xml with id {} as failed due to {}",
+            SqlString::from(xml.id),
+            e
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use log::{error, info, warn};
+use sqlx::{MySql, Pool};
+use std::env;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{task, time};
+
+use crate::schema::job::Job;
+use crate::schema::xml_parse::{XmlParse, XmlStatus};
+use crate::schema::CRUD;
+use crate::utility::env::positive_or;
+use crate::utility::field_mapping::FieldMapping;
+use crate::utility::parser::{failed_import_status, import_status, parse, FileFormat, ParseError};
+use crate::utility::upload;
+use crate::State;
+
+/// How long a worker holds a job without renewing it, after which another worker may take over
+const LEASE_SECS: u64 = 60;
+
+/// How often an idle worker looks for jobs
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many jobs an idle worker looks at, in case others claim the first ones
+const CLAIM_BATCH: u64 = 10;
+
+/// How long a failed job waits before its second attempt, doubling for each attempt after
+const RETRY_DELAY_SECS: u64 = 30;
+
+/// The longest a failed job waits before it is tried again
+const MAX_RETRY_DELAY_SECS: u64 = 15 * 60;
+
+/// Queues an import of the upload recorded as `xml_id`, which any worker of any replica may run
+pub async fn enqueue_import(pool: &Pool<MySql>, xml_id: u64) -> Result<Job, sqlx::Error> {
+    let mut job = Job::import(xml_id, get_job_max_attempts());
+    job.id = Some(job.insert(pool).await?);
+    info!("Enqueued job {} to import xml {}", job.get_id(), xml_id);
+    Ok(job)
+}
+
+/// Queues an import of the upload recorded as `xml_id` already claimed by this process, for
+/// callers that run it with [run_now] rather than leave it to a worker, which may be on a host
+/// that can't read the upload
+pub async fn enqueue_import_here(pool: &Pool<MySql>, xml_id: u64) -> Result<Job, sqlx::Error> {
+    let mut job = Job::import(xml_id, get_job_max_attempts());
+    job.insert_claimed(pool, &owner("inline"), LEASE_SECS)
+        .await?;
+    info!(
+        "Enqueued job {} to import xml {} here",
+        job.get_id(),
+        xml_id
+    );
+    Ok(job)
+}
+
+/// Starts `JOB_WORKERS` workers, each running one job at a time
+pub fn spawn_workers(state: State) {
+    let workers = get_job_workers();
+    info!("Starting {} job workers", workers);
+    for worker in 0..workers {
+        task::spawn(work(state.clone(), owner(&worker.to_string())));
+    }
+}
+
+/// Runs a job queued by [enqueue_import_here] in this process, for callers that wait for the
+/// import. Returns false if its lease was lost to a worker part way. A failed attempt is queued
+/// again as it would be by a worker, so it is retried once the API is running
+pub async fn run_now(state: &State, job: Job) -> bool {
+    run(state, job).await
+}
+
+/// Who holds a lease. Pids repeat across containers and restarts, the host and start time tell
+/// them apart
+fn owner(worker: &str) -> String {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let host = env::var("HOSTNAME").unwrap_or_else(|_| String::from("localhost"));
+    format!("{}:{}:{}:{}", host, process::id(), started, worker)
+}
+
+async fn work(state: State, owner: String) {
+    loop {
+        match claim_next(&state.pool, &owner).await {
+            Ok(Some(job)) => {
+                run(&state, job).await;
+            }
+            Ok(None) => time::sleep(POLL_INTERVAL).await,
+            Err(e) => {
+                error!("Worker {} failed to claim a job due to {}", owner, e);
+                time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+async fn claim_next(pool: &Pool<MySql>, owner: &str) -> Result<Option<Job>, sqlx::Error> {
+    for mut job in Job::get_claimable(pool, CLAIM_BATCH).await? {
+        if job.claim(pool, owner, LEASE_SECS).await? {
+            return Ok(Some(job));
+        }
+    }
+    Ok(None)
+}
+
+/// Runs the job while renewing its lease, then records how it went. A job whose lease is lost
+/// is abandoned, as another worker is about to run it, and false is returned
+async fn run(state: &State, mut job: Job) -> bool {
+    let id = job.get_id();
+    info!(
+        "Running job {} attempt {} of {}",
+        id, job.attempts, job.max_attempts
+    );
+
+    let last_attempt = job.attempts >= job.max_attempts;
+    let result = if job.attempts > job.max_attempts {
+        // Only reachable when a worker died during the last attempt
+        give_up(state, &job).await;
+        Err(format!("Gave up after {} attempts", job.max_attempts))
+    } else {
+        let runner = run_import(state, &job, last_attempt);
+        tokio::pin!(runner);
+        let mut heartbeat = time::interval(Duration::from_secs(LEASE_SECS / 3));
+        heartbeat.tick().await;
+        loop {
+            tokio::select! {
+                result = &mut runner => break result,
+                _ = heartbeat.tick() => match job.renew(&state.pool, LEASE_SECS).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        warn!("Lost the lease on job {}, abandoning it", id);
+                        return false;
+                    }
+                    Err(e) => error!("Failed to renew the lease on job {} due to {}", id, e),
+                },
+            }
+        }
+    };
+
+    let (status, error, delay_secs) = release_as(result, last_attempt, job.attempts);
+    if let Some(e) = &error {
+        error!(
+            "Job {} failed due to {}, now {} for at least {}s",
+            id, e, status, delay_secs
+        );
+    }
+    match job.release(&state.pool, status, error, delay_secs).await {
+        Ok(true) => info!("Job {} is {}", id, status),
+        Ok(false) => warn!("Job {} was taken over before it could be released", id),
+        Err(e) => error!("Failed to release job {} as {} due to {}", id, status, e),
+    }
+    true
+}
+
+/// Parses and pays the upload. The upload is kept until the import either finishes or runs out
+/// of attempts, so an attempt that fails part way can be resumed
+async fn run_import(state: &State, job: &Job, last_attempt: bool) -> Result<(), String> {
+    let xml_id = job.xml_id.ok_or("Import job without an xml")?;
+    let mut xml = XmlParse::get_by_id(&state.pool, xml_id)
+        .await
+        .map_err(|e| format!("Failed to get xml {} due to {}", xml_id, e))?
+        .ok_or_else(|| format!("Xml {} does not exist", xml_id))?;
+
+    let path = upload::upload_path(xml_id);
+    match xml.set_status(&state.pool, XmlStatus::Processing).await {
+        Ok(true) => {}
+        Ok(false) => {
+            // Cancelled or already over, there is nothing left to import
+            info!("Skipping import of xml {} as it is {}", xml_id, xml.status);
+            upload::remove(&path).await;
+            return Ok(());
+        }
+        Err(e) => return Err(format!("Failed to start xml {} due to {}", xml_id, e)),
+    }
+
+    let format = xml
+        .format
+        .as_deref()
+        .and_then(FileFormat::from_name)
+        .unwrap_or_else(|| FileFormat::detect(None, &xml.filename));
+    let parsed = match FieldMapping::load(xml.profile.as_deref()) {
+        Ok(mapping) => match parse(
+            &state.pool,
+            state.method.as_ref(),
+            &path,
+            format,
+            &mapping,
+            &mut xml,
+        )
+        .await
+        {
+            // Already marked Cancelled by whoever cancelled it, nothing is left to retry
+            Err(ParseError::Cancelled { .. }) => {
+                upload::remove(&path).await;
+                return Ok(());
+            }
+            parsed => parsed.map_err(|e| e.to_string()),
+        },
+        Err(e) => Err(e.to_string()),
+    };
+
+    let status = match &parsed {
+        Ok(_) => import_status(&state.pool, xml_id)
+            .await
+            .map_err(|e| format!("Failed to get the outcome of xml {} due to {}", xml_id, e))?,
+        // Rows paid by this or an earlier attempt stay paid
+        Err(_) if last_attempt => failed_import_status(&state.pool, xml_id)
+            .await
+            .map_err(|e| format!("Failed to get the outcome of xml {} due to {}", xml_id, e))?,
+        // Queued again for the next attempt
+        Err(_) => XmlStatus::Queued,
+    };
+    if status.is_final() {
+        upload::remove(&path).await;
+    }
+    match xml.set_status(&state.pool, status).await {
+        Ok(true) => {}
+        Ok(false) => warn!(
+            "Xml {} could not become {} from {}",
+            xml_id, status, xml.status
+        ),
+        Err(e) => error!(
+            "Failed to set xml with id {} as {} due to {}",
+            xml_id, status, e
+        ),
+    }
+    parsed.map(|_| ())
+}
+
+/// The status, error and retry delay a job is released with after `attempts` attempts, the
+/// last of which ended with `result`
+fn release_as(
+    result: Result<(), String>,
+    last_attempt: bool,
+    attempts: u64,
+) -> (&'static str, Option<String>, u64) {
+    match result {
+        Ok(()) => (Job::SUCCEEDED, None, 0),
+        Err(e) if last_attempt => (Job::FAILED, Some(e), 0),
+        Err(e) => (Job::ENQUEUED, Some(e), retry_delay_secs(attempts)),
+    }
+}
+
+/// How long to wait before trying a job again after `attempts` failed attempts, so an API that
+/// is down isn't hit by every queued import as fast as workers can claim them
+fn retry_delay_secs(attempts: u64) -> u64 {
+    let doublings = attempts.saturating_sub(1).min(16) as u32;
+    RETRY_DELAY_SECS
+        .saturating_mul(2u64.pow(doublings))
+        .min(MAX_RETRY_DELAY_SECS)
+}
+
+async fn give_up(state: &State, job: &Job) {
+    let xml_id = match job.xml_id {
+        Some(xml_id) => xml_id,
+        None => return,
+    };
+    upload::remove(&upload::upload_path(xml_id)).await;
+    if let Ok(Some(mut xml)) = XmlParse::get_by_id(&state.pool, xml_id).await {
+        let status = failed_import_status(&state.pool, xml_id)
+            .await
+            .unwrap_or(XmlStatus::Failed);
+        if let Err(e) = xml.set_status(&state.pool, status).await {
+            error!(
+                "Failed to set xml with id {} as {} due to {}",
+                xml_id, status, e
+            );
+        }
+    }
+}
+
+fn get_job_workers() -> usize {
+    positive_or("JOB_WORKERS", 2)
+}
+
+fn get_job_max_attempts() -> u64 {
+    positive_or("JOB_MAX_ATTEMPTS", 3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_cap() {
+        assert_eq!(retry_delay_secs(0), 30);
+        assert_eq!(retry_delay_secs(1), 30);
+        assert_eq!(retry_delay_secs(2), 60);
+        assert_eq!(retry_delay_secs(3), 120);
+        assert_eq!(retry_delay_secs(5), 480);
+        assert_eq!(retry_delay_secs(6), MAX_RETRY_DELAY_SECS);
+        assert_eq!(retry_delay_secs(1000), MAX_RETRY_DELAY_SECS);
+        assert_eq!(retry_delay_secs(u64::MAX), MAX_RETRY_DELAY_SECS);
+    }
+
+    #[test]
+    fn successes_are_released_as_succeeded() {
+        assert_eq!(release_as(Ok(()), false, 1), (Job::SUCCEEDED, None, 0));
+        assert_eq!(release_as(Ok(()), true, 3), (Job::SUCCEEDED, None, 0));
+    }
+
+    #[test]
+    fn failures_are_queued_again_until_the_last_attempt() {
+        let error = || Err(String::from("Method is down"));
+        assert_eq!(
+            release_as(error(), false, 1),
+            (Job::ENQUEUED, Some(String::from("Method is down")), 30)
+        );
+        assert_eq!(
+            release_as(error(), false, 2),
+            (Job::ENQUEUED, Some(String::from("Method is down")), 60)
+        );
+        assert_eq!(
+            release_as(error(), true, 3),
+            (Job::FAILED, Some(String::from("Method is down")), 0)
+        );
+    }
+
+    #[test]
+    fn owners_tell_workers_and_processes_apart() {
+        env::set_var("HOSTNAME", "replica-1");
+        let first = owner("0");
+        let second = owner("1");
+        assert_ne!(first, second);
+
+        let parts: Vec<&str> = first.split(':').collect();
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts[0], "replica-1");
+        assert_eq!(parts[1], process::id().to_string());
+        assert!(parts[2].parse::<u128>().unwrap() > 0);
+        assert_eq!(parts[3], "0");
+    }
+}
@@ -0,0 +1,370 @@
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read};
+use std::vec;
+use xml::common::TextPosition;
+
+use crate::schema::address::Address;
+use crate::schema::employee::Employee;
+use crate::schema::money::Money;
+use crate::schema::payee::Payee;
+use crate::schema::payor::Payor;
+use crate::schema::transaction::Transaction;
+use crate::utility::parser::{validate_row, ParseError, ParsedRow, RawValues, RowFailure};
+
+/// One payment in a JSON batch, the amount is a string so it is parsed exactly like the XML's
+/// `<Amount>`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchRecord {
+    pub employee: Employee,
+    pub payor: Payor,
+    pub address: Option<Address>,
+    pub payee: Payee,
+    pub amount: String,
+}
+
+impl BatchRecord {
+    fn into_row(
+        self,
+        row_index: u64,
+        position: TextPosition,
+        raw: RawValues,
+    ) -> Result<ParsedRow, ParseError> {
+        let amount = Money::parse_amount(&self.amount)
+            .map_err(|e| ParseError::invalid_value("amount", &self.amount, e, position))?;
+
+        // Ids are only ever assigned by us, never taken from the caller
+        let mut employee = self.employee;
+        employee.method_id = None;
+        let mut payor = self.payor;
+        payor.method_id = None;
+        payor.address_id = None;
+        let mut payee = self.payee;
+        payee.method_id = None;
+        let address = self.address.map(|mut address| {
+            address.id = None;
+            address
+        });
+
+        let mut transaction = Transaction::new();
+        transaction.amount = Some(amount);
+
+        let row = ParsedRow {
+            row_index,
+            position,
+            raw,
+            employee,
+            payor,
+            address,
+            payee,
+            transaction,
+        };
+        validate_row(&row)?;
+        Ok(row)
+    }
+}
+
+enum Records<R: Read> {
+    /// A JSON array has to be read whole, each element is still checked on its own
+    Array(vec::IntoIter<serde_json::Value>),
+    Lines(std::io::Lines<BufReader<R>>),
+}
+
+/// Reads [BatchRecord]s from either a JSON array or newline delimited JSON, one record a line.
+/// Yields the same rows as [crate::utility::parser::RowReader]
+pub struct JsonRowReader<R: Read> {
+    records: Records<R>,
+    row_index: u64,
+    line: u64,
+    failure: Option<RowFailure>,
+}
+
+impl<R: Read> JsonRowReader<R> {
+    pub fn array(reader: R) -> Self {
+        let (values, failure) =
+            match serde_json::from_reader::<_, Vec<serde_json::Value>>(BufReader::new(reader)) {
+                Ok(values) => (values, None),
+                Err(e) => {
+                    error!("Failed to read json batch due to {}", e);
+                    let position = TextPosition {
+                        row: (e.line() as u64).saturating_sub(1),
+                        column: (e.column() as u64).saturating_sub(1),
+                    };
+                    let failure = RowFailure {
+                        row_index: 0,
+                        position,
+                        raw: RawValues::new(),
+                        error: ParseError::Malformed {
+                            reason: e.to_string(),
+                            position,
+                        },
+                    };
+                    (vec![], Some(failure))
+                }
+            };
+
+        Self {
+            records: Records::Array(values.into_iter()),
+            row_index: 0,
+            line: 0,
+            failure,
+        }
+    }
+
+    pub fn lines(reader: R) -> Self {
+        Self {
+            records: Records::Lines(BufReader::new(reader).lines()),
+            row_index: 0,
+            line: 0,
+            failure: None,
+        }
+    }
+
+    fn next_record(&mut self) -> Option<(TextPosition, Result<serde_json::Value, String>)> {
+        match &mut self.records {
+            Records::Array(values) => Some((TextPosition::new(), Ok(values.next()?))),
+            Records::Lines(lines) => loop {
+                let line = lines.next()?;
+                let position = TextPosition {
+                    row: self.line,
+                    column: 0,
+                };
+                self.line += 1;
+                match line {
+                    Ok(line) if line.trim().is_empty() => continue,
+                    Ok(line) => {
+                        let value = serde_json::from_str(&line).map_err(|e| e.to_string());
+                        return Some((position, value));
+                    }
+                    Err(e) => {
+                        // The rest of the body can't be trusted after a read failure
+                        self.records = Records::Array(vec![].into_iter());
+                        return Some((position, Err(e.to_string())));
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl<R: Read> Iterator for JsonRowReader<R> {
+    type Item = Result<ParsedRow, RowFailure>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(failure) = self.failure.take() {
+            return Some(Err(failure));
+        }
+
+        let (position, value) = match self.next_record() {
+            Some(next) => next,
+            None => {
+                info!("End of document");
+                return None;
+            }
+        };
+        self.row_index += 1;
+
+        let mut raw = RawValues::new();
+        let result = match value {
+            Ok(value) => {
+                flatten("", &value, &mut raw);
+                match serde_json::from_value::<BatchRecord>(value) {
+                    Ok(record) => record.into_row(self.row_index, position, raw.clone()),
+                    Err(e) => Err(ParseError::Malformed {
+                        reason: e.to_string(),
+                        position,
+                    }),
+                }
+            }
+            Err(reason) => Err(ParseError::Malformed { reason, position }),
+        };
+
+        Some(result.map_err(|error| RowFailure {
+            row_index: self.row_index,
+            position,
+            raw,
+            error,
+        }))
+    }
+}
+
+/// Collects every value of a record keyed by its path, e.g. `payor/address/zip`
+fn flatten(path: &str, value: &serde_json::Value, raw: &mut RawValues) {
+    let key = |name: &str| match path {
+        "" => name.to_string(),
+        _ => format!("{}/{}", path, name),
+    };
+    match value {
+        serde_json::Value::Null => {}
+        serde_json::Value::String(text) => {
+            raw.insert(path.to_string(), text.clone());
+        }
+        serde_json::Value::Object(fields) => {
+            for (name, value) in fields {
+                flatten(&key(name), value, raw);
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                flatten(&key(&index.to_string()), value, raw);
+            }
+        }
+        other => {
+            raw.insert(path.to_string(), other.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(employee_id: &str, amount: &str) -> String {
+        format!(
+            r#"{{"employee": {{"dunkin_id": "{}", "dunkin_branch": "BRC1", "first_name": "Ada", "last_name": "Lovelace"}}, "payor": {{"dunkin_id": "CORP1"}}, "payee": {{"plaid_id": "ins_1", "loan_account_number": 12345}}, "amount": "{}"}}"#,
+            employee_id, amount
+        )
+    }
+
+    fn lines(body: &str) -> Vec<Result<ParsedRow, RowFailure>> {
+        JsonRowReader::lines(body.as_bytes()).collect()
+    }
+
+    fn array(body: &str) -> Vec<Result<ParsedRow, RowFailure>> {
+        JsonRowReader::array(body.as_bytes()).collect()
+    }
+
+    #[test]
+    fn reads_every_field_of_a_record() {
+        let body = r#"{"employee": {"dunkin_id": "EMP1", "method_id": "ent_theirs", "dunkin_branch": "BRC1", "first_name": "Ada", "last_name": "Lovelace", "dob": "12-10-1815", "phone_number": "+15555550100"}, "payor": {"dunkin_id": "CORP1", "method_id": "ent_theirs", "aba_routing": 148386123, "account_number": 12719660, "payor_name": "Dunkin' Donuts LLC", "dba": "Dunkin' Donuts", "ein": "32120240", "address_id": 9}, "address": {"id": 9, "line1": "999 Hayes Lights", "city": "Kerlukemouth", "state": "IA", "zip": 67485}, "payee": {"plaid_id": "ins_1", "method_id": "acc_theirs", "loan_account_number": 12345}, "amount": "$1,234.50"}"#;
+        let rows = lines(body);
+        assert_eq!(rows.len(), 1);
+        let row = rows[0].as_ref().unwrap();
+
+        assert_eq!(row.row_index, 1);
+        assert_eq!(row.employee.dunkin_id.as_deref(), Some("EMP1"));
+        assert_eq!(row.employee.dunkin_branch.as_deref(), Some("BRC1"));
+        assert_eq!(row.employee.first_name.as_deref(), Some("Ada"));
+        assert_eq!(row.employee.last_name.as_deref(), Some("Lovelace"));
+        assert_eq!(row.employee.dob.as_deref(), Some("12-10-1815"));
+        assert_eq!(row.employee.phone_number.as_deref(), Some("+15555550100"));
+        assert_eq!(row.payor.dunkin_id.as_deref(), Some("CORP1"));
+        assert_eq!(row.payor.aba_routing, Some(148386123));
+        assert_eq!(row.payor.account_number, Some(12719660));
+        assert_eq!(row.payor.payor_name.as_deref(), Some("Dunkin' Donuts LLC"));
+        assert_eq!(row.payor.dba.as_deref(), Some("Dunkin' Donuts"));
+        assert_eq!(row.payor.ein.as_deref(), Some("32120240"));
+        let address = row.address.as_ref().unwrap();
+        assert_eq!(address.line1.as_deref(), Some("999 Hayes Lights"));
+        assert_eq!(address.city.as_deref(), Some("Kerlukemouth"));
+        assert_eq!(address.state.as_deref(), Some("IA"));
+        assert_eq!(address.zip, Some(67485));
+        assert_eq!(row.payee.plaid_id.as_deref(), Some("ins_1"));
+        assert_eq!(row.payee.loan_account_number, Some(12345));
+        assert_eq!(row.transaction.amount, Some(Money::from_cents(123_450)));
+
+        // Ids are ours to assign
+        assert_eq!(row.employee.method_id, None);
+        assert_eq!(row.payor.method_id, None);
+        assert_eq!(row.payor.address_id, None);
+        assert_eq!(address.id, None);
+        assert_eq!(row.payee.method_id, None);
+
+        assert_eq!(
+            row.raw.get("address/zip").map(String::as_str),
+            Some("67485")
+        );
+        assert_eq!(row.raw.get("amount").map(String::as_str), Some("$1,234.50"));
+    }
+
+    #[test]
+    fn reads_arrays_and_lines_alike() {
+        let records = [record("EMP1", "$1.00"), record("EMP2", "$2.00")];
+        for rows in [
+            array(&format!("[{}]", records.join(","))),
+            lines(&records.join("\n")),
+        ] {
+            assert_eq!(rows.len(), 2);
+            let second = rows[1].as_ref().unwrap();
+            assert_eq!(second.row_index, 2);
+            assert_eq!(second.employee.dunkin_id.as_deref(), Some("EMP2"));
+            assert_eq!(second.transaction.amount, Some(Money::from_cents(200)));
+        }
+    }
+
+    #[test]
+    fn fails_rows_alone_with_their_line() {
+        let body = [
+            record("EMP1", "$1.00"),
+            String::new(),
+            record("EMP2", "lots"),
+            record("EMP3", "$3.00").replace(r#""plaid_id": "ins_1", "#, ""),
+            record("EMP4", "$4.00"),
+        ]
+        .join("\n");
+        let rows = lines(&body);
+        assert_eq!(rows.len(), 4);
+        assert!(rows[0].is_ok());
+
+        let invalid = rows[1].as_ref().unwrap_err();
+        assert_eq!(invalid.row_index, 2);
+        assert!(matches!(
+            &invalid.error,
+            ParseError::InvalidValue { element, value, .. } if element == "amount" && value == "lots"
+        ));
+        assert_eq!(invalid.raw.get("amount").map(String::as_str), Some("lots"));
+        // The blank line still counts towards the line number
+        assert_eq!(invalid.to_row_error(None).line, 3);
+
+        let missing = rows[2].as_ref().unwrap_err();
+        assert_eq!(missing.row_index, 3);
+        assert!(matches!(
+            &missing.error,
+            ParseError::MissingElement { element, .. } if element == "Payee/PlaidId"
+        ));
+        assert_eq!(missing.to_row_error(None).line, 4);
+
+        assert_eq!(rows[3].as_ref().unwrap().row_index, 4);
+    }
+
+    #[test]
+    fn a_malformed_line_fails_only_that_row() {
+        let body = [
+            record("EMP1", "$1.00"),
+            String::from(r#"{"employee": {"#),
+            String::from(r#"{"employee": "EMP2"}"#),
+            record("EMP3", "$3.00"),
+        ]
+        .join("\n");
+        let rows = lines(&body);
+        assert_eq!(rows.len(), 4);
+        assert!(rows[0].is_ok());
+        for (index, row) in rows[1..3].iter().enumerate() {
+            let failure = row.as_ref().unwrap_err();
+            assert_eq!(failure.row_index, index as u64 + 2);
+            assert!(matches!(failure.error, ParseError::Malformed { .. }));
+        }
+        assert_eq!(rows[3].as_ref().unwrap().row_index, 4);
+    }
+
+    #[test]
+    fn a_malformed_array_fails_the_file() {
+        let rows = array(&format!("[{},", record("EMP1", "$1.00")));
+        assert_eq!(rows.len(), 1);
+        let failure = rows[0].as_ref().unwrap_err();
+        assert_eq!(failure.row_index, 0);
+        assert!(matches!(failure.error, ParseError::Malformed { .. }));
+    }
+
+    #[test]
+    fn empty_batches_have_no_rows() {
+        assert!(array("[]").is_empty());
+        assert!(lines("").is_empty());
+        assert!(lines("\n  \n").is_empty());
+        // An empty body isn't an array at all
+        assert!(matches!(
+            array("").as_slice(),
+            [Err(RowFailure { row_index: 0, .. })]
+        ));
+    }
+}
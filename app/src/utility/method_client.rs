@@ -4,22 +4,25 @@ use crate::entities::entity::Entity;
 use crate::entities::entity_response::EntityResponse;
 use crate::entities::payment::Payment;
 use crate::entities::payment_response::PaymentResponse;
+use crate::utility::env::{parse_or, positive_or};
 use crate::utility::method_client::Error::{
-    HTTPError, IOError, RequestBuilderError, SerializeError,
+    ConnectError, HTTPError, IOError, RequestBuilderError, SerializeError, TimeoutError,
 };
+use async_trait::async_trait;
 use axum::http;
-use hyper::{Body, Client, Method, Request, StatusCode};
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::stream::{self, Stream};
+use hyper::body::Bytes;
+use hyper::client::HttpConnector;
+use hyper::header::RETRY_AFTER;
+use hyper::{Body, Client, HeaderMap, Method, Request, StatusCode};
 use hyper_tls::HttpsConnector;
-use lazy_static::lazy_static;
-use log::{debug, error};
+use log::{debug, error, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::env;
-
-static METHOD_BASE_URL: &'static str = "https://dev.methodfi.com";
-lazy_static! {
-    static ref METHOD_API_KEY: String = env::var("METHOD_API_KEY").expect("METHOD_API_KEY was set");
-}
+use std::collections::VecDeque;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -27,10 +30,14 @@ pub enum Error {
     HTTPError(StatusCode, String),
     #[error("RequestBuilder Error: {0}")]
     RequestBuilderError(#[source] http::Error),
+    #[error("Connect Error: {0}")]
+    ConnectError(#[source] Box<dyn std::error::Error>), // The request was never sent
     #[error("IO Error: {0}")]
     IOError(#[source] Box<dyn std::error::Error>),
     #[error("Serialization Error: {0}")]
     SerializeError(#[source] Box<dyn std::error::Error>),
+    #[error("Timeout Error: no response after {0:?}")]
+    TimeoutError(Duration),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,150 +61,630 @@ struct ErrorDetails {
     pub message: String,
 }
 
-pub async fn post_source_account(account: SourceAccount) -> Result<AccountResponse, Error> {
-    let response: MethodResponse<AccountResponse> =
-        generic_request(Method::POST, "accounts", Some(account), HashMap::new()).await?;
-    Ok(response.data)
-}
+/// Everything we ask of Method. [MethodClient] talks to a real Method environment, anything
+/// else implementing this can stand in for it
+#[async_trait]
+pub trait MethodApi: Send + Sync {
+    async fn post_source_account(&self, account: SourceAccount) -> Result<AccountResponse, Error>;
+
+    async fn post_dest_account(&self, account: DestAccount) -> Result<AccountResponse, Error>;
 
-pub async fn post_dest_account(account: DestAccount) -> Result<AccountResponse, Error> {
-    let response: MethodResponse<AccountResponse> =
-        generic_request(Method::POST, "accounts", Some(account), HashMap::new()).await?;
-    Ok(response.data)
+    /// Payments are only retried when `idempotency_key` is set, as Method then pays once
+    /// however many times it is sent
+    async fn post_payment(
+        &self,
+        payment: Payment,
+        idempotency_key: Option<&str>,
+    ) -> Result<PaymentResponse, Error>;
+
+    /// A single page of payments, [list_payments] reads all of them
+    async fn get_payments(&self, query: &ListQuery) -> Result<Vec<PaymentResponse>, Error>;
+
+    async fn post_entity(&self, entity: Entity) -> Result<EntityResponse, Error>;
+
+    /// A single page of entities, [list_entities] reads all of them
+    async fn get_entities(&self, query: &ListQuery) -> Result<Vec<EntityResponse>, Error>;
 }
 
-pub async fn post_payment(payment: Payment) -> Result<PaymentResponse, Error> {
-    let response: MethodResponse<PaymentResponse> =
-        generic_request(Method::POST, "payments", Some(payment), HashMap::new()).await?;
-    Ok(response.data)
+/// Items per page when a [ListQuery] doesn't ask for a number
+pub const DEFAULT_PAGE_LIMIT: u32 = 100;
+
+/// Paging and filters for Method's list endpoints. The dates are inclusive and compared with
+/// when each object was created
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListQuery {
+    pub page: Option<u32>, // Counted from 1
+    pub page_limit: Option<u32>,
+    pub from_date: Option<NaiveDate>,
+    pub to_date: Option<NaiveDate>,
 }
 
-pub async fn get_payments(
-    query_params: HashMap<&str, &str>,
-) -> Result<Vec<PaymentResponse>, Error> {
-    let response =
-        generic_request::<Vec<PaymentResponse>, u32>(Method::GET, "payments", None, query_params)
-            .await?;
-    Ok(response.data)
+impl ListQuery {
+    fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![];
+        if let Some(page) = self.page {
+            params.push(("page", page.to_string()));
+        }
+        if let Some(page_limit) = self.page_limit {
+            params.push(("page_limit", page_limit.to_string()));
+        }
+        if let Some(from_date) = self.from_date {
+            params.push(("from_date", from_date.format("%Y-%m-%d").to_string()));
+        }
+        if let Some(to_date) = self.to_date {
+            params.push(("to_date", to_date.format("%Y-%m-%d").to_string()));
+        }
+        params
+    }
 }
 
-pub async fn post_entity(entity: Entity) -> Result<EntityResponse, Error> {
-    let response: MethodResponse<EntityResponse> =
-        generic_request(Method::POST, "entities", Some(entity), HashMap::new()).await?;
-    Ok(response.data)
+/// Every payment matching `query`, starting from `query.page`. Pages are only requested as
+/// the stream is read, so stopping early saves the rest
+pub fn list_payments(
+    method: &dyn MethodApi,
+    query: ListQuery,
+) -> impl Stream<Item = Result<PaymentResponse, Error>> + Send + '_ {
+    paginate(query, move |query| async move {
+        method.get_payments(&query).await
+    })
 }
 
-pub async fn get_entities(query_params: HashMap<&str, &str>) -> Result<Vec<EntityResponse>, Error> {
-    let response =
-        generic_request::<Vec<EntityResponse>, u32>(Method::GET, "entities", None, query_params)
-            .await?;
-    Ok(response.data)
+/// Every entity matching `query`, read a page at a time like [list_payments]
+pub fn list_entities(
+    method: &dyn MethodApi,
+    query: ListQuery,
+) -> impl Stream<Item = Result<EntityResponse, Error>> + Send + '_ {
+    paginate(query, move |query| async move {
+        method.get_entities(&query).await
+    })
 }
 
-async fn generic_request<ResponseType, RequestType: Serialize>(
-    method: Method,
-    endpoint: &str,
-    body: Option<RequestType>,
-    query_params: HashMap<&str, &str>,
-) -> Result<MethodResponse<ResponseType>, Error>
+/// Fetches the next page once the last one has been read. Method may return fewer items than
+/// the limit before the end, so only an empty page is the end. The stream ends after the first
+/// error
+fn paginate<'a, T, F, Fut>(
+    query: ListQuery,
+    fetch: F,
+) -> impl Stream<Item = Result<T, Error>> + Send + 'a
 where
-    ResponseType: for<'a> Deserialize<'a> + Serialize,
+    T: Send + 'a,
+    F: Fn(ListQuery) -> Fut + Send + 'a,
+    Fut: Future<Output = Result<Vec<T>, Error>> + Send + 'a,
 {
-    let mut uri = format!("{}/{}", METHOD_BASE_URL, endpoint);
+    let page_limit = query.page_limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    let first = ListQuery {
+        page: Some(query.page.unwrap_or(1)),
+        page_limit: Some(page_limit),
+        ..query
+    };
+    stream::unfold(
+        (fetch, VecDeque::new(), Some(first)),
+        move |(fetch, mut items, mut next)| async move {
+            loop {
+                if let Some(item) = items.pop_front() {
+                    return Some((Ok(item), (fetch, items, next)));
+                }
+                let query = next.take()?;
+                match fetch(query.clone()).await {
+                    Ok(page) => {
+                        if !page.is_empty() {
+                            next = Some(ListQuery {
+                                page: query.page.map(|page| page + 1),
+                                ..query
+                            });
+                        }
+                        items.extend(page);
+                    }
+                    Err(e) => return Some((Err(e), (fetch, items, None))),
+                }
+            }
+        },
+    )
+}
+
+/// Where and how to reach Method
+#[derive(Debug, Clone)]
+pub struct MethodConfig {
+    pub base_url: String,
+    pub api_key: Option<String>, // Sent as a bearer token, a local stand-in may not need one
+    pub connect_timeout: Duration,
+    pub request_timeout: Duration, // For the whole request, from connecting to reading the body
+    pub max_attempts: u32,         // Including the first, 1 never retries
+    pub backoff: Duration,         // Longest wait before the first retry, doubling after each
+}
 
-    if !query_params.is_empty() {
-        uri.push_str("?");
+impl MethodConfig {
+    pub fn from_env() -> Self {
+        Self {
+            base_url: get_method_base_url(),
+            api_key: get_method_api_key(),
+            connect_timeout: get_timeout("METHOD_CONNECT_TIMEOUT_SECS", 10),
+            request_timeout: get_timeout("METHOD_TIMEOUT_SECS", 30),
+            max_attempts: get_method_max_attempts(),
+            backoff: get_method_backoff(),
+        }
     }
-    for param in query_params {
-        uri.push_str(param.0);
-        uri.push_str("=");
-        uri.push_str(param.1);
+}
+
+/// Client for the Method API. Connections are pooled, so one client should be shared by
+/// everything that talks to Method
+pub struct MethodClient {
+    config: MethodConfig,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl MethodClient {
+    pub fn new(config: MethodConfig) -> Self {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_connect_timeout(Some(config.connect_timeout));
+        let client = Client::builder().build::<_, Body>(HttpsConnector::new_with_connector(http));
+
+        Self { config, client }
     }
 
-    let entity_type = std::any::type_name::<RequestType>();
-    let result_entity_type = std::any::type_name::<ResponseType>();
+    pub fn from_env() -> Self {
+        Self::new(MethodConfig::from_env())
+    }
+}
 
-    debug!("Sending {} request to {}", method.as_str(), uri);
-    let https_connector = HttpsConnector::new();
-    let client = Client::builder().build::<_, hyper::Body>(https_connector);
+#[async_trait]
+impl MethodApi for MethodClient {
+    async fn post_source_account(&self, account: SourceAccount) -> Result<AccountResponse, Error> {
+        let response: MethodResponse<AccountResponse> = self
+            .generic_request(
+                Method::POST,
+                "accounts",
+                Some(account),
+                &[],
+                // Not idempotent, see Retry::Unsent
+                Retry::Unsent,
+                None,
+            )
+            .await?;
+        Ok(response.data)
+    }
 
-    let json: String = match serde_json::to_string(&body) {
-        Ok(string) => string,
-        Err(e) => {
-            error!(
-                "Failed to deserialize to string, from {} due to '{}'",
-                entity_type, e
-            );
-            return Err(SerializeError(Box::new(e)));
-        }
-    };
+    async fn post_dest_account(&self, account: DestAccount) -> Result<AccountResponse, Error> {
+        let response: MethodResponse<AccountResponse> = self
+            .generic_request(
+                Method::POST,
+                "accounts",
+                Some(account),
+                &[],
+                // Not idempotent, see Retry::Unsent
+                Retry::Unsent,
+                None,
+            )
+            .await?;
+        Ok(response.data)
+    }
 
-    let mut builder = Request::builder()
-        .method(method)
-        .uri(uri.clone())
-        .header("Authorization", format!("Bearer {}", *METHOD_API_KEY));
+    async fn post_payment(
+        &self,
+        payment: Payment,
+        idempotency_key: Option<&str>,
+    ) -> Result<PaymentResponse, Error> {
+        let retry = match idempotency_key {
+            Some(_) => Retry::Idempotent,
+            None => Retry::Never,
+        };
+        let response: MethodResponse<PaymentResponse> = self
+            .generic_request(
+                Method::POST,
+                "payments",
+                Some(payment),
+                &[],
+                retry,
+                idempotency_key,
+            )
+            .await?;
+        Ok(response.data)
+    }
 
-    if body.is_some() {
-        builder = builder
-            .header("Content-Type", "application/json")
-            .header("Content-Length", json.len());
+    async fn get_payments(&self, query: &ListQuery) -> Result<Vec<PaymentResponse>, Error> {
+        let response = self
+            .generic_request::<Vec<PaymentResponse>, u32>(
+                Method::GET,
+                "payments",
+                None,
+                &query.params(),
+                Retry::Idempotent,
+                None,
+            )
+            .await?;
+        Ok(response.data)
     }
 
-    let request = match builder.body(Body::from(json)) {
-        Ok(req) => req,
-        Err(e) => {
-            error!("Failed to build request to '{}' due to '{}'", uri, e);
-            return Err(RequestBuilderError(e));
-        }
-    };
+    async fn post_entity(&self, entity: Entity) -> Result<EntityResponse, Error> {
+        let response: MethodResponse<EntityResponse> = self
+            .generic_request(
+                Method::POST,
+                "entities",
+                Some(entity),
+                &[],
+                // Not idempotent, see Retry::Unsent
+                Retry::Unsent,
+                None,
+            )
+            .await?;
+        Ok(response.data)
+    }
 
-    let result = match client.request(request).await {
-        Ok(res) => res,
-        Err(e) => {
-            error!("Failed to send request to '{}' due to '{}'", uri, e);
-            return Err(IOError(Box::new(e)));
-        }
-    };
+    async fn get_entities(&self, query: &ListQuery) -> Result<Vec<EntityResponse>, Error> {
+        let response = self
+            .generic_request::<Vec<EntityResponse>, u32>(
+                Method::GET,
+                "entities",
+                None,
+                &query.params(),
+                Retry::Idempotent,
+                None,
+            )
+            .await?;
+        Ok(response.data)
+    }
+}
 
-    let status = result.status();
-    debug!("status: {}", status);
+impl MethodClient {
+    async fn generic_request<ResponseType, RequestType: Serialize>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        body: Option<RequestType>,
+        query_params: &[(&str, String)],
+        retry: Retry,
+        idempotency_key: Option<&str>,
+    ) -> Result<MethodResponse<ResponseType>, Error>
+    where
+        ResponseType: for<'a> Deserialize<'a> + Serialize,
+    {
+        let mut uri = format!("{}/{}", self.config.base_url, endpoint);
 
-    let buf = match hyper::body::to_bytes(result).await {
-        Ok(bytes) => bytes,
-        Err(e) => {
-            error!(
-                "Failed to create bytes from {} due to '{}'",
-                result_entity_type, e
+        if !query_params.is_empty() {
+            uri.push('?');
+            uri.push_str(
+                &form_urlencoded::Serializer::new(String::new())
+                    .extend_pairs(query_params)
+                    .finish(),
             );
-            return Err(SerializeError(Box::new(e)));
         }
-    };
 
-    let ret = if status.is_success() {
-        match serde_json::from_slice::<MethodResponse<ResponseType>>(&buf) {
-            Ok(account_response) => Ok(account_response),
+        let entity_type = std::any::type_name::<RequestType>();
+        let result_entity_type = std::any::type_name::<ResponseType>();
+
+        debug!("Sending {} request to {}", method.as_str(), uri);
+        let json: String = match serde_json::to_string(&body) {
+            Ok(string) => string,
             Err(e) => {
                 error!(
-                    "Failed to serialize from bytes, to {} due to '{}'",
-                    result_entity_type, e
+                    "Failed to deserialize to string, from {} due to '{}'",
+                    entity_type, e
                 );
-                Err(SerializeError(Box::new(e)))
+                return Err(SerializeError(Box::new(e)));
             }
-        }
-    } else {
-        let mut cause = String::from("");
-        match serde_json::from_slice::<MethodResponse<MethodError>>(&buf) {
-            Ok(failure_response) => {
-                cause = failure_response.data.error.message;
+        };
+
+        let mut attempt = 1;
+        let reply = loop {
+            // Scoped so the result, which isn't Send, is dropped before sleeping
+            let delay = {
+                let result = self
+                    .send(&method, &uri, &json, body.is_some(), idempotency_key)
+                    .await;
+                let retry_after = result.as_ref().ok().and_then(|reply| reply.retry_after);
+                if attempt >= self.config.max_attempts
+                    || !retry.allows(&result)
+                    || retry_after.is_some_and(|after| after > MAX_RETRY_AFTER)
+                {
+                    break result?;
+                }
+                let failure = match &result {
+                    Ok(reply) => reply.status.to_string(),
+                    Err(e) => e.to_string(),
+                };
+                let delay = retry_after.unwrap_or_else(|| self.backoff(attempt));
+                warn!(
+                    "Attempt {} of {} to {} {} failed with {}, retrying in {:?}",
+                    attempt, self.config.max_attempts, method, uri, failure, delay
+                );
+                delay
+            };
+            time::sleep(delay).await;
+            attempt += 1;
+        };
+        let status = reply.status;
+        let buf = reply.body;
+
+        let ret = if status.is_success() {
+            match serde_json::from_slice::<MethodResponse<ResponseType>>(&buf) {
+                Ok(account_response) => Ok(account_response),
+                Err(e) => {
+                    error!(
+                        "Failed to serialize from bytes, to {} due to '{}'",
+                        result_entity_type, e
+                    );
+                    Err(SerializeError(Box::new(e)))
+                }
             }
+        } else {
+            let mut cause = String::from("");
+            match serde_json::from_slice::<MethodResponse<MethodError>>(&buf) {
+                Ok(failure_response) => {
+                    cause = failure_response.data.error.message;
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to deserialize error response after Http Failure due to {}",
+                        e
+                    );
+                }
+            };
+            Err(HTTPError(status, cause))
+        };
+        ret
+    }
+
+    /// Sends the request once
+    async fn send(
+        &self,
+        method: &Method,
+        uri: &str,
+        json: &str,
+        has_body: bool,
+        idempotency_key: Option<&str>,
+    ) -> Result<Reply, Error> {
+        let mut builder = Request::builder().method(method.clone()).uri(uri);
+        if let Some(api_key) = &self.config.api_key {
+            builder = builder.header("Authorization", format!("Bearer {}", api_key));
+        }
+        if let Some(key) = idempotency_key {
+            builder = builder.header("Idempotency-Key", key);
+        }
+
+        if has_body {
+            builder = builder
+                .header("Content-Type", "application/json")
+                .header("Content-Length", json.len());
+        }
+
+        let request = match builder.body(Body::from(json.to_string())) {
+            Ok(req) => req,
             Err(e) => {
-                error!(
-                    "Failed to deserialize error response after Http Failure due to {}",
-                    e
-                );
+                error!("Failed to build request to '{}' due to '{}'", uri, e);
+                return Err(RequestBuilderError(e));
             }
         };
-        Err(HTTPError(status, cause))
-    };
-    ret
+
+        let timeout = self.config.request_timeout;
+        let response = time::timeout(timeout, async {
+            let result = match self.client.request(request).await {
+                Ok(res) => res,
+                Err(e) if e.is_connect() => {
+                    error!("Failed to connect to '{}' due to '{}'", uri, e);
+                    return Err(ConnectError(Box::new(e)));
+                }
+                Err(e) => {
+                    error!("Failed to send request to '{}' due to '{}'", uri, e);
+                    return Err(IOError(Box::new(e)));
+                }
+            };
+
+            let status = result.status();
+            debug!("status: {}", status);
+            let retry_after = retry_after(result.headers());
+
+            match hyper::body::to_bytes(result).await {
+                Ok(body) => Ok(Reply {
+                    status,
+                    retry_after,
+                    body,
+                }),
+                Err(e) => {
+                    error!("Failed to read the response from '{}' due to '{}'", uri, e);
+                    Err(SerializeError(Box::new(e)))
+                }
+            }
+        })
+        .await;
+        match response {
+            Ok(response) => response,
+            Err(_) => {
+                error!("Request to '{}' timed out after {:?}", uri, timeout);
+                Err(TimeoutError(timeout))
+            }
+        }
+    }
+
+    /// Exponential backoff with full jitter, so requests that failed together don't all retry
+    /// together
+    fn backoff(&self, attempt: u32) -> Duration {
+        backoff_ceiling(self.config.backoff, attempt).mul_f64(rand::random::<f64>())
+    }
+}
+
+/// The longest wait before retrying after `attempt`, doubling from `backoff` up to [MAX_BACKOFF]
+fn backoff_ceiling(backoff: Duration, attempt: u32) -> Duration {
+    backoff
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+        .min(MAX_BACKOFF)
+}
+
+/// Which failures a request may be sent again after
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Retry {
+    /// Sending it again has no further effect, so every transient failure is retried
+    Idempotent,
+    /// Only retried when Method can't have acted on it, it couldn't connect or was rate limited.
+    /// Entities and accounts are created without an idempotency key, since there is no record of
+    /// one to send again like `PaymentAttempts` for payments. A retry after a timeout could create
+    /// a second one, so a failure Method may have acted on fails the row instead
+    Unsent,
+    /// Payments without an idempotency key, which could be paid twice
+    Never,
+}
+
+impl Retry {
+    fn allows(self, result: &Result<Reply, Error>) -> bool {
+        match result {
+            Ok(reply) if reply.status == StatusCode::TOO_MANY_REQUESTS => self != Retry::Never,
+            Ok(reply) => reply.status.is_server_error() && self == Retry::Idempotent,
+            Err(ConnectError(_)) => self != Retry::Never,
+            Err(IOError(_)) | Err(TimeoutError(_)) => self == Retry::Idempotent,
+            Err(_) => false,
+        }
+    }
+}
+
+/// A response as it came back, before it is read as a success or a failure
+struct Reply {
+    status: StatusCode,
+    retry_after: Option<Duration>,
+    body: Bytes,
+}
+
+/// Longest wait between attempts when Method doesn't say how long to wait
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Longest `Retry-After` that is waited out, rather than failing the request
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// `Retry-After` as either a number of seconds or an HTTP date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+fn get_method_base_url() -> String {
+    let base_url: String = parse_or("METHOD_BASE_URL", String::from("https://dev.methodfi.com"));
+    base_url.trim_end_matches('/').to_string()
+}
+
+fn get_method_api_key() -> Option<String> {
+    let api_key: String = parse_or("METHOD_API_KEY", String::new());
+    if api_key.is_empty() {
+        warn!("Requests to Method are sent without an API key");
+        return None;
+    }
+    Some(api_key)
+}
+
+fn get_timeout(name: &str, default: u64) -> Duration {
+    Duration::from_secs(positive_or(name, default))
+}
+
+fn get_method_max_attempts() -> u32 {
+    positive_or("METHOD_MAX_ATTEMPTS", 3)
+}
+
+fn get_method_backoff() -> Duration {
+    Duration::from_millis(parse_or("METHOD_BACKOFF_MS", 250))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::TryStreamExt;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let backoff = Duration::from_millis(250);
+        assert_eq!(backoff_ceiling(backoff, 1), Duration::from_millis(250));
+        assert_eq!(backoff_ceiling(backoff, 2), Duration::from_millis(500));
+        assert_eq!(backoff_ceiling(backoff, 3), Duration::from_secs(1));
+        assert_eq!(backoff_ceiling(backoff, 7), MAX_BACKOFF);
+        assert_eq!(backoff_ceiling(backoff, u32::MAX), MAX_BACKOFF);
+        assert_eq!(backoff_ceiling(Duration::MAX, 2), MAX_BACKOFF);
+        assert_eq!(backoff_ceiling(Duration::ZERO, 5), Duration::ZERO);
+    }
+
+    #[test]
+    fn reads_retry_after_seconds_and_dates() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static(" 3 "));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(3)));
+
+        // A date that has passed is no wait at all
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    fn reply(status: StatusCode) -> Result<Reply, Error> {
+        Ok(Reply {
+            status,
+            retry_after: None,
+            body: Bytes::new(),
+        })
+    }
+
+    #[test]
+    fn only_retries_what_cant_be_applied_twice() {
+        let rate_limited = reply(StatusCode::TOO_MANY_REQUESTS);
+        let unavailable = reply(StatusCode::SERVICE_UNAVAILABLE);
+        let bad_request = reply(StatusCode::BAD_REQUEST);
+        let unconnected: Result<Reply, Error> = Err(ConnectError("refused".into()));
+        let timed_out: Result<Reply, Error> = Err(TimeoutError(Duration::from_secs(1)));
+
+        for (result, idempotent, unsent, never) in [
+            (&rate_limited, true, true, false),
+            (&unavailable, true, false, false),
+            (&bad_request, false, false, false),
+            (&unconnected, true, true, false),
+            (&timed_out, true, false, false),
+        ] {
+            assert_eq!(Retry::Idempotent.allows(result), idempotent);
+            assert_eq!(Retry::Unsent.allows(result), unsent);
+            assert_eq!(Retry::Never.allows(result), never);
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_until_an_empty_page() {
+        // A short page isn't the end, only an empty one is
+        let pages = [vec![1, 2], vec![3], vec![]];
+        let items: Vec<u32> = paginate(
+            ListQuery {
+                page_limit: Some(2),
+                ..Default::default()
+            },
+            move |query| {
+                let page = pages[query.page.unwrap() as usize - 1].clone();
+                async move { Ok(page) }
+            },
+        )
+        .try_collect()
+        .await
+        .unwrap();
+        assert_eq!(items, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn stops_paging_after_an_error() {
+        let mut items = Box::pin(paginate(ListQuery::default(), |query| async move {
+            match query.page {
+                Some(1) => Ok(vec![1]),
+                _ => Err(TimeoutError(Duration::from_secs(1))),
+            }
+        }));
+        assert_eq!(items.try_next().await.unwrap(), Some(1));
+        assert!(items.try_next().await.is_err());
+        assert!(items.try_next().await.unwrap().is_none());
+    }
 }
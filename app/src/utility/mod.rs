@@ -1,2 +1,11 @@
+pub mod csv_parser;
+pub mod duplicates;
+pub mod env;
+pub mod field_mapping;
+pub mod imports;
+pub mod jobs;
+pub mod json_parser;
 pub mod method_client;
 pub mod parser;
+pub mod upload;
+pub mod xsd;
@@ -1,24 +1,130 @@
-use axum::body::Bytes;
-use hyper::body::Buf;
-use log::{debug, error, info, trace, warn};
+use futures_util::future;
+use futures_util::stream::{self, Stream, StreamExt};
+use log::{error, info, trace, warn};
+use serde::Serialize;
 use sqlx::{MySql, Pool};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
+use std::fs::File;
 use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task;
 
-use crate::entities;
 use crate::entities::Persist;
 use crate::schema::employee::Employee;
+use crate::schema::held_payment::HeldPayment;
+use crate::schema::money::Money;
 use crate::schema::payee::Payee;
 use crate::schema::payor::Payor;
+use crate::schema::row_error::RowError;
 use crate::schema::transaction::Transaction;
-use crate::schema::{address, transaction};
-use crate::utility::parser::ParseError::IOError;
+use crate::schema::transaction_row::{RowResult, TransactionRow};
+use crate::schema::xml_parse::{XmlParse, XmlStatus};
+use crate::schema::{address, transaction, SqlString, CRUD};
+use crate::utility::csv_parser::CsvRowReader;
+use crate::utility::duplicates::{DuplicateRules, PaymentHistory, PaymentKey};
+use crate::utility::env::positive_or;
+use crate::utility::field_mapping::FieldMapping;
+use crate::utility::json_parser::JsonRowReader;
+use crate::utility::method_client::MethodApi;
+use xml::common::{Position, TextPosition};
 use xml::reader::{EventReader, XmlEvent};
 
 #[derive(Debug)]
 pub enum ParseError {
     UnexpectedElement,
     IOError,
+    Malformed {
+        reason: String,
+        position: TextPosition,
+    },
+    InvalidValue {
+        element: String,
+        value: String,
+        reason: String,
+        position: TextPosition,
+    },
+    MissingElement {
+        element: String,
+        position: TextPosition,
+    },
+    Rejected {
+        element: String,
+        reason: String,
+        method_error: Option<String>, // Set when Method is what rejected it
+    },
+    SchemaViolation {
+        element: String,
+        reason: String,
+        position: TextPosition,
+    },
+    Cancelled {
+        paid: u64, // Rows paid before the import stopped, by this run or earlier ones
+    },
+}
+
+impl ParseError {
+    pub(crate) fn invalid_value(
+        element: &str,
+        value: &str,
+        reason: impl fmt::Display,
+        position: TextPosition,
+    ) -> Self {
+        ParseError::InvalidValue {
+            element: element.to_string(),
+            value: value.to_string(),
+            reason: reason.to_string(),
+            position,
+        }
+    }
+
+    pub(crate) fn missing(element: &str, position: TextPosition) -> Self {
+        ParseError::MissingElement {
+            element: element.to_string(),
+            position,
+        }
+    }
+
+    fn rejected(element: &str, reason: impl fmt::Display, method_error: Option<String>) -> Self {
+        ParseError::Rejected {
+            element: element.to_string(),
+            reason: reason.to_string(),
+            method_error,
+        }
+    }
+
+    /// The message Method gave, when the row was rejected by Method rather than by us
+    pub fn method_error(&self) -> Option<&str> {
+        match self {
+            ParseError::Rejected { method_error, .. } => method_error.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The element responsible for the error, if it can be narrowed down past the row
+    pub fn element(&self) -> Option<&str> {
+        match self {
+            ParseError::InvalidValue { element, .. }
+            | ParseError::MissingElement { element, .. }
+            | ParseError::Rejected { element, .. }
+            | ParseError::SchemaViolation { element, .. } => Some(element.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn position(&self) -> Option<TextPosition> {
+        match self {
+            ParseError::Malformed { position, .. }
+            | ParseError::InvalidValue { position, .. }
+            | ParseError::MissingElement { position, .. }
+            | ParseError::SchemaViolation { position, .. } => Some(*position),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
@@ -26,63 +132,1285 @@ impl fmt::Display for ParseError {
         match self {
             ParseError::UnexpectedElement => write!(f, "Unexpected element error"),
             ParseError::IOError => write!(f, "IO error"),
+            ParseError::Malformed { reason, .. } => write!(f, "Malformed file: {}", reason),
+            ParseError::InvalidValue {
+                element,
+                value,
+                reason,
+                ..
+            } => write!(f, "Invalid value '{}' for {}: {}", value, element, reason),
+            ParseError::MissingElement { element, .. } => {
+                write!(f, "Missing required element {}", element)
+            }
+            ParseError::Rejected {
+                element, reason, ..
+            } => {
+                write!(f, "{} was rejected: {}", element, reason)
+            }
+            ParseError::SchemaViolation { reason, .. } => {
+                write!(f, "Schema violation: {}", reason)
+            }
+            ParseError::Cancelled { paid } => {
+                write!(f, "Cancelled after {} rows were paid", paid)
+            }
+        }
+    }
+}
+
+/// Every value of a row as it appeared in the file, keyed by its element path or column header
+pub type RawValues = BTreeMap<String, String>;
+
+/// A `<row>` read from the file, before anything has been sent to Method or MySQL
+#[derive(Serialize, Debug, Clone)]
+pub struct ParsedRow {
+    pub row_index: u64, // 1-based position of the <row> in the file
+    #[serde(skip)]
+    pub position: TextPosition,
+    #[serde(skip)]
+    pub raw: RawValues,
+    pub employee: Employee,
+    pub payor: Payor,
+    pub address: Option<address::Address>,
+    pub payee: Payee,
+    pub transaction: Transaction,
+}
+
+#[derive(Debug)]
+pub struct RowFailure {
+    pub row_index: u64,
+    pub position: TextPosition,
+    pub raw: RawValues,
+    pub error: ParseError,
+}
+
+impl RowFailure {
+    pub fn to_row_error(&self, xml_id: Option<u64>) -> RowError {
+        // TextPosition is 0-based, people reading the report count from 1
+        let position = self.error.position().unwrap_or(self.position);
+        RowError::new(
+            xml_id,
+            self.row_index,
+            position.row + 1,
+            position.column + 1,
+            self.error
+                .element()
+                .unwrap_or(Transaction::XML_IDENTIFIER)
+                .to_string(),
+            self.error.to_string(),
+        )
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ValidationReport {
+    pub rows: Vec<ParsedRow>,
+    pub payment_map_acc: HashMap<String, Money>,
+    pub payment_map_branch: HashMap<String, Money>,
+    pub errors: Vec<RowError>,
+}
+
+impl ValidationReport {
+    /// Totals the amounts of `rows` by payor and by branch
+    fn total(&mut self) {
+        self.payment_map_acc.clear();
+        self.payment_map_branch.clear();
+        for row in &self.rows {
+            let amount = row.transaction.amount.expect("Amount was validated");
+            *self
+                .payment_map_acc
+                .entry(row.payor.dunkin_id.clone().expect("Payor id was validated"))
+                .or_default() += amount;
+            *self
+                .payment_map_branch
+                .entry(
+                    row.employee
+                        .dunkin_branch
+                        .clone()
+                        .expect("Dunkin branch was validated"),
+                )
+                .or_default() += amount;
+        }
+    }
+}
+
+/// The formats a payroll file can be uploaded in
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Xml,
+    Csv,
+    Json,
+    Ndjson,
+}
+
+impl FileFormat {
+    /// Picks the format from the upload's content type, falling back to the file extension.
+    /// Anything unrecognised is treated as XML, which is what uploads were before CSV existed
+    pub fn detect(content_type: Option<&str>, filename: &str) -> Self {
+        let content_type = content_type
+            .and_then(|c| c.split(';').next())
+            .map(|c| c.trim().to_lowercase());
+        if let Some(format) = content_type.as_deref().and_then(Self::from_content_type) {
+            return format;
+        }
+
+        let filename = filename.to_lowercase();
+        if filename.ends_with(".csv") {
+            FileFormat::Csv
+        } else if filename.ends_with(".json") {
+            FileFormat::Json
+        } else if filename.ends_with(".ndjson") || filename.ends_with(".jsonl") {
+            FileFormat::Ndjson
+        } else {
+            FileFormat::Xml
         }
     }
+
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type {
+            "text/csv" | "application/csv" => Some(FileFormat::Csv),
+            "text/xml" | "application/xml" => Some(FileFormat::Xml),
+            "application/json" => Some(FileFormat::Json),
+            "application/x-ndjson" | "application/ndjson" | "application/jsonl" => {
+                Some(FileFormat::Ndjson)
+            }
+            _ => None,
+        }
+    }
+
+    /// How the format is stored against an import, so it can be read the same way when resumed
+    pub fn name(self) -> &'static str {
+        match self {
+            FileFormat::Xml => "xml",
+            FileFormat::Csv => "csv",
+            FileFormat::Json => "json",
+            FileFormat::Ndjson => "ndjson",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xml" => Some(FileFormat::Xml),
+            "csv" => Some(FileFormat::Csv),
+            "json" => Some(FileFormat::Json),
+            "ndjson" => Some(FileFormat::Ndjson),
+            _ => None,
+        }
+    }
+
+    /// `mapping` only applies to XML, the other formats have fixed field names
+    pub fn rows<R: Read + Send + 'static>(
+        self,
+        reader: R,
+        mapping: &FieldMapping,
+    ) -> Box<dyn Iterator<Item = Result<ParsedRow, RowFailure>> + Send> {
+        match self {
+            FileFormat::Xml => Box::new(RowReader::new(reader, mapping.clone())),
+            FileFormat::Csv => Box::new(CsvRowReader::new(reader)),
+            FileFormat::Json => Box::new(JsonRowReader::array(reader)),
+            FileFormat::Ndjson => Box::new(JsonRowReader::lines(reader)),
+        }
+    }
+}
+
+/// Yields every record in the document, `<row>` unless the mapping says otherwise. Stops after
+/// the first malformed XML error since nothing after it can be trusted
+pub struct RowReader<R: Read> {
+    parser: EventReader<BufReader<R>>,
+    mapping: FieldMapping,
+    row_index: u64,
+    finished: bool,
 }
 
-impl From<entities::Error> for ParseError {
-    fn from(value: entities::Error) -> Self {
-        error!("Error : {}", value);
-        IOError
+impl<R: Read> RowReader<R> {
+    pub fn new(reader: R, mapping: FieldMapping) -> Self {
+        Self {
+            parser: EventReader::new(BufReader::new(reader)), // Buffering is important for performance
+            mapping,
+            row_index: 0,
+            finished: false,
+        }
     }
 }
 
+impl<R: Read> Iterator for RowReader<R> {
+    type Item = Result<ParsedRow, RowFailure>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        loop {
+            match self.parser.next() {
+                Ok(XmlEvent::EndDocument) => {
+                    info!("End of document");
+                    self.finished = true;
+                    return None;
+                }
+                Ok(XmlEvent::StartElement { name, .. }) if name.local_name == self.mapping.row => {
+                    self.row_index += 1;
+                    let position = self.parser.position();
+                    let mut raw = RawValues::new();
+                    let result = parse_transaction(
+                        &mut self.parser,
+                        &self.mapping,
+                        &mut raw,
+                        self.row_index,
+                        position,
+                    )
+                    .and_then(|row| {
+                        validate_row(&row)?;
+                        Ok(row)
+                    });
+                    return Some(match result {
+                        Ok(row) => Ok(ParsedRow { raw, ..row }),
+                        Err(error) => {
+                            if let ParseError::Malformed { .. } = error {
+                                self.finished = true;
+                            }
+                            Err(RowFailure {
+                                row_index: self.row_index,
+                                position,
+                                raw,
+                                error,
+                            })
+                        }
+                    });
+                }
+                // Between rows, so it's a failure of the file rather than of the last row read
+                Err(e) => {
+                    error!("Error parsing document due to {e}");
+                    self.finished = true;
+                    return Some(Err(RowFailure {
+                        row_index: 0,
+                        position: e.position(),
+                        raw: RawValues::new(),
+                        error: ParseError::Malformed {
+                            reason: e.msg().to_string(),
+                            position: e.position(),
+                        },
+                    }));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Parses and pays every row of an import. An import that was interrupted picks up after its
+/// checkpoint, and rows that were already paid, held or failed are never processed again
 pub async fn parse(
     pool: &Pool<MySql>,
-    file: Bytes,
-    xml_id: u64,
+    method: &dyn MethodApi,
+    path: &Path,
+    format: FileFormat,
+    mapping: &FieldMapping,
+    xml: &mut XmlParse,
 ) -> Result<Vec<Transaction>, ParseError> {
-    let file = BufReader::new(file.reader()); // Buffering is important for performance
-    let mut transactions: Vec<Transaction> = vec![];
-    let mut parser = EventReader::new(file);
-    loop {
-        match parser.next() {
-            Ok(XmlEvent::EndDocument) => {
-                info!("End of document");
-                break;
+    let xml_id = xml.id.expect("Id was set");
+    let concurrency = get_parse_concurrency();
+    let progress = Progress::load(pool, xml).await.map_err(|e| {
+        error!("Failed to get progress of xml {} due to {}", xml_id, e);
+        ParseError::IOError
+    })?;
+    if progress.resuming {
+        info!(
+            "Resuming xml {} after row {} with {} workers",
+            xml_id, progress.checkpoint, concurrency
+        );
+    } else {
+        info!("Parsing xml {} with {} workers", xml_id, concurrency);
+    }
+
+    // First pass, everything the payments depend on is created once however many rows share it
+    // Every row is recorded as it is read, so each one can be traced whatever happens to it
+    let mut collector = EntityCollector::default();
+    let mut recorded: HashSet<u64> = progress.recorded.clone();
+    let mut rows = read_rows(path, format, mapping).await?;
+    while let Some(row) = rows.next().await {
+        match row {
+            Ok(row) => {
+                collector.add(&row);
+                if recorded.insert(row.row_index) {
+                    record_row(pool, xml_id, row.row_index, &row.raw, None).await;
+                }
             }
-            Ok(XmlEvent::StartElement { name, .. }) => {
-                if name.local_name == Transaction::XML_IDENTIFIER.to_string() {
-                    match parse_transaction(&mut parser, pool, xml_id).await {
-                        Ok(transaction) => {
-                            transactions.push(transaction);
-                        }
-                        Err(e) => {
-                            error!("Transaction failed due to {}, skipping", e)
+            // Failures were already recorded by the run being resumed
+            Err(failure)
+                if progress.recorded.contains(&failure.row_index)
+                    || (progress.resuming && failure.row_index == 0) => {}
+            Err(failure) => {
+                error!(
+                    "Row {} failed due to {}, skipping",
+                    failure.row_index, failure.error
+                );
+                record_row_error(pool, xml_id, &failure).await;
+                // Row 0 is a failure of the file as a whole rather than of any row
+                if failure.row_index > 0 && recorded.insert(failure.row_index) {
+                    let error = failure.error.to_string();
+                    record_row(pool, xml_id, failure.row_index, &failure.raw, Some(error)).await;
+                }
+            }
+        }
+    }
+    let resolved = collector
+        .resolve(pool, method, concurrency)
+        .await
+        .map_err(|e| {
+            error!("Failed to resolve entities of xml {} due to {}", xml_id, e);
+            ParseError::IOError
+        })?;
+
+    // Second pass, failed rows were recorded above so only the valid ones are paid.
+    // `buffered` runs up to `concurrency` rows at once but yields them in file order
+    // Likely duplicates are held rather than paid. A row waits for the last one before it with
+    // the same key, and a row is only remembered once it was paid or held, so the first of a set
+    // of duplicates to go through is the one paid
+    let rules = DuplicateRules::from_env();
+    let employee_ids: Vec<String> = resolved
+        .employees
+        .values()
+        .filter_map(|id| id.clone().ok())
+        .collect();
+    let history = PaymentHistory::load(pool, xml_id, &employee_ids, rules.window_days())
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to get recent payments for xml {} due to {}",
+                xml_id, e
+            );
+            ParseError::IOError
+        })?;
+
+    if is_cancelled(pool, xml_id).await {
+        return Err(cancelled(pool, xml_id).await);
+    }
+
+    // Set once the import is cancelled, rows that haven't started yet are then left alone
+    let cancel = AtomicBool::new(false);
+    let cancel = &cancel;
+    let progress = &progress;
+    let history = Mutex::new(history);
+    let history = &history;
+    let rules = &rules;
+    // Finishes when the latest row with each key is over, however it ended
+    let mut running: HashMap<PaymentKey, oneshot::Receiver<()>> = HashMap::new();
+    let mut transactions: Vec<Transaction> = vec![];
+    let mut held: u64 = 0;
+    let mut skipped: u64 = 0;
+    let mut checkpoints = Checkpoints::new(progress.checkpoint);
+    let mut last_cancel_check = Instant::now();
+    let mut results = read_rows(path, format, mapping)
+        .await?
+        .filter_map(|row| future::ready(row.ok()))
+        .map(|row| {
+            let row_index = row.row_index;
+            let settled = progress.settled.contains(&row_index);
+            let row = resolve_row(xml_id, row, &resolved);
+            let key = row
+                .as_ref()
+                .ok()
+                .and_then(|row| PaymentKey::of(&row.transaction));
+            let (finished, earlier) = match &key {
+                Some(key) => {
+                    let (finished, receiver) = oneshot::channel::<()>();
+                    (Some(finished), running.insert(key.clone(), receiver))
+                }
+                None => (None, None),
+            };
+            async move {
+                // Dropped as the row ends, which lets the next row with its key go ahead
+                let _finished = finished;
+                if let Some(earlier) = earlier {
+                    let _ = earlier.await;
+                }
+                match progress.start(row_index, cancel.load(Ordering::Relaxed)) {
+                    RowStart::Skip => {
+                        // Rows paid or held by an earlier run are still remembered, so later
+                        // rows are compared to them
+                        if let (Some(key), true) = (key, settled) {
+                            remember(history, key, row_index);
                         }
+                        return Ok(RowOutcome::Skipped(row_index));
+                    }
+                    RowStart::Cancel => return Ok(RowOutcome::Cancelled),
+                    RowStart::Run => {}
+                }
+                let row = match row {
+                    Ok(row) => row,
+                    Err(unresolved) => {
+                        let (
+                            row,
+                            element,
+                            Unresolved {
+                                reason,
+                                method_error,
+                            },
+                        ) = *unresolved;
+                        return Err(row.fail(pool, element, reason, method_error).await);
                     }
+                };
+                let duplicate = key.as_ref().and_then(|key| {
+                    let history = history.lock().expect("Payment history lock was poisoned");
+                    rules.check(key, &history)
+                });
+                let outcome = match duplicate {
+                    Some(reason) => hold_row(pool, row, reason).await,
+                    None => pay_row(pool, method, row).await,
+                };
+                if let (Ok(_), Some(key)) = (&outcome, key) {
+                    remember(history, key, row_index);
                 }
+                outcome
             }
-            Err(e) => {
-                error!("Error parsing document due to {e}");
-                break;
+        })
+        .buffered(concurrency);
+
+    while let Some(result) = results.next().await {
+        let row_index = match result {
+            Ok(RowOutcome::Paid(transaction)) => {
+                let row_index = transaction.row_index.unwrap_or_default();
+                transactions.push(transaction);
+                row_index
+            }
+            Ok(RowOutcome::Held(payment)) => {
+                warn!(
+                    "Row {} of xml {} was held due to {}",
+                    payment.row_index, xml_id, payment.reason
+                );
+                held += 1;
+                payment.row_index
+            }
+            Ok(RowOutcome::Skipped(row_index)) => {
+                skipped += 1;
+                row_index
+            }
+            // Not processed, so the checkpoint stays where it is
+            Ok(RowOutcome::Cancelled) => continue,
+            Err(failure) => {
+                error!(
+                    "Row {} failed due to {}, skipping",
+                    failure.row_index, failure.error
+                );
+                record_row_error(pool, xml_id, &failure).await;
+                failure.row_index
+            }
+        };
+
+        // Rows already started are let finish, so none is left paid but unrecorded. The import
+        // may be cancelled through any replica, so it's looked up rather than signalled
+        if !cancel.load(Ordering::Relaxed) && last_cancel_check.elapsed() >= CANCEL_CHECK_INTERVAL {
+            last_cancel_check = Instant::now();
+            if is_cancelled(pool, xml_id).await {
+                info!(
+                    "Xml {} was cancelled, stopping after row {}",
+                    xml_id, row_index
+                );
+                cancel.store(true, Ordering::Relaxed);
             }
-            _ => {}
         }
+
+        if let Some(row_index) = checkpoints.processed(row_index) {
+            checkpoint(pool, xml, row_index).await;
+        }
+    }
+    if let Some(row_index) = checkpoints.finish() {
+        checkpoint(pool, xml, row_index).await;
+    }
+    if cancel.load(Ordering::Relaxed) {
+        return Err(cancelled(pool, xml_id).await);
     }
+    info!(
+        "Paid {} rows of xml {}, held {} as likely duplicates, skipped {} done before",
+        transactions.len(),
+        xml_id,
+        held,
+        skipped
+    );
     Ok(transactions)
 }
 
-async fn parse_transaction<R: Read>(
-    parser: &mut EventReader<BufReader<R>>,
+fn remember(history: &Mutex<PaymentHistory>, key: PaymentKey, row_index: u64) {
+    history
+        .lock()
+        .expect("Payment history lock was poisoned")
+        .remember(key, row_index);
+}
+
+/// Whether the import was cancelled since it started, see [crate::endpoints::xmls::cancel_handler]
+async fn is_cancelled(pool: &Pool<MySql>, xml_id: u64) -> bool {
+    match XmlParse::get_status(pool, xml_id).await {
+        Ok(status) => status == XmlStatus::Cancelled,
+        Err(e) => {
+            error!("Failed to get status of xml {} due to {}", xml_id, e);
+            false
+        }
+    }
+}
+
+async fn cancelled(pool: &Pool<MySql>, xml_id: u64) -> ParseError {
+    let paid = XmlParse::count_transactions(pool, xml_id)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Failed to count payments of xml {} due to {}", xml_id, e);
+            0
+        });
+    info!("Xml {} was cancelled after {} rows were paid", xml_id, paid);
+    ParseError::Cancelled { paid }
+}
+
+/// How a parsed import ended, Failed if none of its rows got anywhere
+pub async fn import_status(pool: &Pool<MySql>, xml_id: u64) -> Result<XmlStatus, sqlx::Error> {
+    if RowError::get_all_by_xml_id(pool, xml_id).await?.is_empty() {
+        return Ok(XmlStatus::Finished);
+    }
+    failed_import_status(pool, xml_id).await
+}
+
+/// How an import that failed ended, PartiallyFailed if any of its rows were paid or held first
+pub async fn failed_import_status(
+    pool: &Pool<MySql>,
+    xml_id: u64,
+) -> Result<XmlStatus, sqlx::Error> {
+    let rows = TransactionRow::get_all_by_xml_id(pool, xml_id).await?;
+    let progressed = rows.iter().any(|row| {
+        [
+            TransactionRow::PAID,
+            TransactionRow::HELD,
+            TransactionRow::DISMISSED,
+        ]
+        .contains(&row.status.as_str())
+    });
+    match progressed {
+        true => Ok(XmlStatus::PartiallyFailed),
+        false => Ok(XmlStatus::Failed),
+    }
+}
+
+/// What an earlier run of the same import got through, nothing for a new import
+#[derive(Debug, Default)]
+struct Progress {
+    resuming: bool,
+    checkpoint: u64,
+    recorded: HashSet<u64>,  // Rows already in TransactionRows
+    processed: HashSet<u64>, // Rows already paid, held or failed
+    settled: HashSet<u64>,   // Rows already paid or held
+}
+
+impl Progress {
+    async fn load(pool: &Pool<MySql>, xml: &XmlParse) -> Result<Self, sqlx::Error> {
+        let xml_id = xml.id.expect("Id was set");
+        let rows = TransactionRow::get_all_by_xml_id(pool, xml_id).await?;
+        // A payment can be made without its row being updated, if the server stopped in between
+        let paid = XmlParse::get_all_transactions_by_xml_id(pool, xml_id)
+            .await?
+            .into_iter()
+            .filter_map(|transaction| transaction.row_index);
+        let held = HeldPayment::get_all_by_xml_id(pool, xml_id)
+            .await?
+            .into_iter()
+            .map(|payment| payment.row_index);
+        Ok(Self::new(xml.checkpoint, &rows, paid.chain(held)))
+    }
+
+    /// `settled` is every row with a payment or a held payment
+    fn new(
+        checkpoint: Option<u64>,
+        rows: &[TransactionRow],
+        settled: impl IntoIterator<Item = u64>,
+    ) -> Self {
+        let mut progress = Self {
+            checkpoint: checkpoint.unwrap_or_default(),
+            settled: settled.into_iter().collect(),
+            ..Default::default()
+        };
+        for row in rows {
+            if row.status != TransactionRow::PARSED {
+                progress.processed.insert(row.row_index);
+            }
+            progress.recorded.insert(row.row_index);
+        }
+        progress.processed.extend(&progress.settled);
+        progress.resuming = checkpoint.is_some() || !progress.recorded.is_empty();
+        progress
+    }
+
+    fn is_done(&self, row_index: u64) -> bool {
+        row_index <= self.checkpoint || self.processed.contains(&row_index)
+    }
+
+    /// What to do with a row as its turn comes, once the import is `cancelled` no more rows are
+    /// started. Rows done before are skipped either way, so nothing about them is lost
+    fn start(&self, row_index: u64, cancelled: bool) -> RowStart {
+        if self.is_done(row_index) {
+            RowStart::Skip
+        } else if cancelled {
+            RowStart::Cancel
+        } else {
+            RowStart::Run
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RowStart {
+    Skip,   // Processed by an earlier run
+    Cancel, // Left alone as the import was cancelled
+    Run,
+}
+
+/// Where the checkpoint of a running import moves to. Rows come back in file order, so once a
+/// row is processed so is everything before it
+#[derive(Debug)]
+struct Checkpoints {
+    at: u64,
+    since: u64, // Rows processed since the checkpoint was last written
+    last_row: u64,
+}
+
+impl Checkpoints {
+    fn new(at: u64) -> Self {
+        Self {
+            at,
+            since: 0,
+            last_row: 0,
+        }
+    }
+
+    /// Notes that `row_index` was processed, returning where to checkpoint if one is due
+    fn processed(&mut self, row_index: u64) -> Option<u64> {
+        self.since += 1;
+        self.last_row = row_index;
+        if self.since < CHECKPOINT_INTERVAL || row_index <= self.at {
+            return None;
+        }
+        self.at = row_index;
+        self.since = 0;
+        Some(row_index)
+    }
+
+    /// Where to checkpoint once the last row was processed, if it moved since the last one
+    fn finish(&self) -> Option<u64> {
+        match self.last_row > self.at {
+            true => Some(self.last_row),
+            false => None,
+        }
+    }
+}
+
+async fn checkpoint(pool: &Pool<MySql>, xml: &mut XmlParse, row_index: u64) {
+    if let Err(e) = xml.set_checkpoint(pool, row_index).await {
+        error!(
+            "Failed to checkpoint xml {} at row {} due to {}",
+            SqlString::from(xml.id),
+            row_index,
+            e
+        );
+    }
+}
+
+/// Reads the file's rows on a blocking thread, so parsing a large file doesn't hold up the
+/// runtime. Reading stays at most [ROW_BUFFER] rows ahead of the caller
+async fn read_rows(
+    path: &Path,
+    format: FileFormat,
+    mapping: &FieldMapping,
+) -> Result<impl Stream<Item = Result<ParsedRow, RowFailure>>, ParseError> {
+    let file = tokio::fs::File::open(path).await.map_err(|e| {
+        error!("Failed to open {} due to {}", path.display(), e);
+        ParseError::IOError
+    })?;
+    let file: File = file.into_std().await;
+    let mapping = mapping.clone();
+    let (sender, mut receiver) = mpsc::channel(ROW_BUFFER);
+    task::spawn_blocking(move || {
+        for row in format.rows(file, &mapping) {
+            // The receiver is gone once the import stops early, nothing is left to read for
+            if sender.blocking_send(row).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(stream::poll_fn(move |cx| receiver.poll_recv(cx)))
+}
+
+/// Runs the same parsing and validation as [parse] without calling Method or writing to MySQL
+pub async fn validate<R: Read + Send + 'static>(
+    pool: &Pool<MySql>,
+    file: R,
+    format: FileFormat,
+    mapping: &FieldMapping,
+) -> Result<ValidationReport, ParseError> {
+    let mapping = mapping.clone();
+    let mut report = task::spawn_blocking(move || validate_offline(file, format, &mapping))
+        .await
+        .map_err(|e| {
+            error!("Failed to validate rows due to {}", e);
+            ParseError::IOError
+        })?;
+
+    // Payors are never created during an import, so a row with an unknown payor would be rejected
+    // A file has a handful of payors over many rows, each is looked up once
+    let payor_ids: HashSet<String> = report
+        .rows
+        .iter()
+        .filter_map(|row| row.payor.dunkin_id.clone())
+        .collect();
+    let known_payors: HashSet<String> = if payor_ids.is_empty() {
+        HashSet::new()
+    } else {
+        let payor_ids: Vec<String> = payor_ids.into_iter().collect();
+        Payor::get_in(pool, HashMap::from([("DunkinId", payor_ids)]))
+            .await
+            .map_err(|e| {
+                error!("Failed to get payors due to {}", e);
+                ParseError::IOError
+            })?
+            .into_iter()
+            .filter_map(|payor| payor.dunkin_id)
+            .collect()
+    };
+
+    let (rows, rejected): (Vec<ParsedRow>, Vec<ParsedRow>) = report
+        .rows
+        .into_iter()
+        .partition(|row| known_payors.contains(row.payor.dunkin_id.as_deref().unwrap_or("")));
+    for row in rejected {
+        let failure = RowFailure {
+            row_index: row.row_index,
+            position: row.position,
+            raw: row.raw,
+            error: ParseError::Rejected {
+                element: Payor::XML_IDENTIFIER.to_string(),
+                reason: format!(
+                    "Payor {} is not one of the valid payors",
+                    SqlString::from(row.payor.dunkin_id)
+                ),
+                method_error: None,
+            },
+        };
+        report.errors.push(failure.to_row_error(None));
+    }
+    report.errors.sort_by_key(|e| e.row_index);
+    report.rows = rows;
+    report.total();
+
+    Ok(report)
+}
+
+/// Parses and checks every row without any network access, so payors are not checked against
+/// the DB as they are by [validate]
+pub fn validate_offline<R: Read + Send + 'static>(
+    file: R,
+    format: FileFormat,
+    mapping: &FieldMapping,
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    for row in format.rows(file, mapping) {
+        match row {
+            Ok(row) => report.rows.push(row),
+            Err(failure) => report.errors.push(failure.to_row_error(None)),
+        }
+    }
+    report.total();
+    report
+}
+
+async fn record_row(
     pool: &Pool<MySql>,
     xml_id: u64,
-) -> Result<transaction::Transaction, ParseError> {
+    row_index: u64,
+    raw: &RawValues,
+    error: Option<String>,
+) {
+    let raw_values = serde_json::to_string(raw).unwrap_or_default();
+    let row = TransactionRow::new(xml_id, row_index, raw_values, error);
+    if let Err(e) = row.insert(pool).await {
+        error!(
+            "Failed to record row {} of xml {} due to {}",
+            row_index, xml_id, e
+        );
+    }
+}
+
+async fn record_row_result(pool: &Pool<MySql>, xml_id: u64, row_index: u64, result: RowResult) {
+    if let Err(e) = TransactionRow::set_result(pool, xml_id, row_index, &result).await {
+        error!(
+            "Failed to record {} for row {} of xml {} due to {}",
+            result.status, row_index, xml_id, e
+        );
+    }
+}
+
+pub(crate) async fn record_row_error(pool: &Pool<MySql>, xml_id: u64, failure: &RowFailure) {
+    if let Err(e) = failure.to_row_error(Some(xml_id)).insert(pool).await {
+        error!(
+            "Failed to record error for row {} of xml {} due to {}",
+            failure.row_index, xml_id, e
+        );
+    }
+}
+
+/// How many parsed rows can wait to be processed before reading the file pauses
+const ROW_BUFFER: usize = 256;
+
+/// How many rows are processed between checkpoints, each of which is a write to MySQL
+const CHECKPOINT_INTERVAL: u64 = 100;
+
+/// How often a running import looks up whether it was cancelled, each of which is a read of MySQL
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+fn get_parse_concurrency() -> usize {
+    positive_or("PARSE_CONCURRENCY", 8)
+}
+
+/// Distinct entities referenced by a file, keyed by the field their table is looked up by
+#[derive(Default)]
+struct EntityCollector {
+    employees: HashMap<String, Employee>,
+    payors: HashMap<String, Payor>,
+    // The first employee seen paying into a payee becomes its holder
+    payees: HashMap<String, (Payee, String)>,
+    addresses: HashMap<String, address::Address>,
+}
+
+/// Why an entity could not be created, with Method's message when it was Method that refused
+#[derive(Debug, Clone)]
+struct Unresolved {
+    reason: String,
+    method_error: Option<String>,
+}
+
+impl Unresolved {
+    fn new(reason: String) -> Self {
+        Self {
+            reason,
+            method_error: None,
+        }
+    }
+}
+
+/// What each collected entity resolved to, either its id or why it could not be created
+#[derive(Default)]
+struct ResolvedEntities {
+    employees: HashMap<String, Result<String, Unresolved>>,
+    payors: HashMap<String, Result<String, Unresolved>>,
+    payees: HashMap<String, Result<String, Unresolved>>,
+    addresses: HashMap<String, Result<u64, Unresolved>>,
+}
+
+fn address_key(address: &address::Address) -> String {
+    format!(
+        "{}:{}:{}:{}",
+        SqlString::from(address.line1.clone()),
+        SqlString::from(address.city.clone()),
+        SqlString::from(address.state.clone()),
+        SqlString::from(address.zip)
+    )
+}
+
+impl EntityCollector {
+    fn add(&mut self, row: &ParsedRow) {
+        let employee_id = row
+            .employee
+            .dunkin_id
+            .clone()
+            .expect("DunkinId was validated");
+        self.employees
+            .entry(employee_id.clone())
+            .or_insert_with(|| row.employee.clone());
+        self.payors
+            .entry(row.payor.dunkin_id.clone().expect("DunkinId was validated"))
+            .or_insert_with(|| row.payor.clone());
+        self.payees
+            .entry(row.payee.plaid_id.clone().expect("PlaidId was validated"))
+            .or_insert_with(|| (row.payee.clone(), employee_id));
+        if let Some(address) = &row.address {
+            self.addresses
+                .entry(address_key(address))
+                .or_insert_with(|| address.clone());
+        }
+    }
+
+    /// Loads the entities that already exist with one query per table, then creates the rest
+    async fn resolve(
+        self,
+        pool: &Pool<MySql>,
+        method: &dyn MethodApi,
+        concurrency: usize,
+    ) -> Result<ResolvedEntities, sqlx::Error> {
+        let mut resolved = ResolvedEntities::default();
+        info!(
+            "Resolving {} employees, {} payors, {} payees and {} addresses",
+            self.employees.len(),
+            self.payors.len(),
+            self.payees.len(),
+            self.addresses.len()
+        );
+
+        // Addresses that differ past the first line share it
+        let lines: HashSet<String> = self
+            .addresses
+            .values()
+            .filter_map(|a| a.line1.clone())
+            .collect();
+        let lines: Vec<String> = lines.into_iter().collect();
+        for existing in address::Address::get_in(pool, HashMap::from([("Line1", lines)])).await? {
+            resolved
+                .addresses
+                .entry(address_key(&existing))
+                .or_insert(Ok(existing.id.expect("Id was set")));
+        }
+        let missing = self
+            .addresses
+            .into_iter()
+            .filter(|(key, _)| !resolved.addresses.contains_key(key))
+            .map(|(key, address)| (key, address, ()))
+            .collect();
+        resolved.addresses.extend(
+            create_all(
+                pool,
+                method,
+                missing,
+                concurrency,
+                |a: &address::Address| a.id.expect("Id was set"),
+            )
+            .await,
+        );
+
+        let employee_ids: Vec<String> = self.employees.keys().cloned().collect();
+        for existing in Employee::get_in(pool, HashMap::from([("DunkinId", employee_ids)])).await? {
+            resolved
+                .employees
+                .entry(existing.dunkin_id.clone().unwrap())
+                .or_insert(Ok(existing.method_id.expect("MethodId was set")));
+        }
+        let missing = self
+            .employees
+            .into_iter()
+            .filter(|(key, _)| !resolved.employees.contains_key(key))
+            .map(|(key, employee)| (key, employee, ()))
+            .collect();
+        resolved.employees.extend(
+            create_all(pool, method, missing, concurrency, |e: &Employee| {
+                e.method_id.clone().expect("MethodId was set")
+            })
+            .await,
+        );
+
+        let payor_ids: Vec<String> = self.payors.keys().cloned().collect();
+        for existing in Payor::get_in(pool, HashMap::from([("DunkinId", payor_ids)])).await? {
+            resolved
+                .payors
+                .entry(existing.dunkin_id.clone().unwrap())
+                .or_insert(Ok(existing.method_id.expect("MethodId was set")));
+        }
+        let missing = self
+            .payors
+            .into_iter()
+            .filter(|(key, _)| !resolved.payors.contains_key(key))
+            .map(|(key, payor)| (key, payor, ()))
+            .collect();
+        resolved.payors.extend(
+            create_all(pool, method, missing, concurrency, |p: &Payor| {
+                p.method_id.clone().expect("MethodId was set")
+            })
+            .await,
+        );
+
+        let plaid_ids: Vec<String> = self.payees.keys().cloned().collect();
+        for existing in Payee::get_in(pool, HashMap::from([("PlaidId", plaid_ids)])).await? {
+            resolved
+                .payees
+                .entry(existing.plaid_id.clone().unwrap())
+                .or_insert(Ok(existing.method_id.expect("MethodId was set")));
+        }
+        let mut missing = vec![];
+        for (key, (payee, employee_id)) in self.payees {
+            if resolved.payees.contains_key(&key) {
+                continue;
+            }
+            match lookup(&resolved.employees, &employee_id) {
+                Ok(holder) => missing.push((key, payee, holder)),
+                Err(holder) => {
+                    let reason =
+                        format!("Holder {} was not created: {}", employee_id, holder.reason);
+                    let unresolved = Unresolved {
+                        reason,
+                        method_error: holder.method_error,
+                    };
+                    resolved.payees.insert(key, Err(unresolved));
+                }
+            }
+        }
+        resolved.payees.extend(
+            create_all(pool, method, missing, concurrency, |p: &Payee| {
+                p.method_id.clone().expect("MethodId was set")
+            })
+            .await,
+        );
+
+        Ok(resolved)
+    }
+}
+
+/// Creates every entity, up to `concurrency` at a time, returning each key with the new id or
+/// the reason it failed
+async fn create_all<T, Id>(
+    pool: &Pool<MySql>,
+    method: &dyn MethodApi,
+    entities: Vec<(String, T, T::Dependencies)>,
+    concurrency: usize,
+    get_id: fn(&T) -> Id,
+) -> Vec<(String, Result<Id, Unresolved>)>
+where
+    T: Persist + Send,
+    T::Dependencies: Send,
+{
+    stream::iter(entities)
+        .map(|(key, mut entity, dependency)| async move {
+            let created = match entity.create(pool, method, dependency).await {
+                Ok(()) => Ok(get_id(&entity)),
+                Err(e) => {
+                    error!("Failed to create {} due to {}", key, e);
+                    Err(Unresolved {
+                        reason: e.to_string(),
+                        method_error: e.method_message().map(str::to_string),
+                    })
+                }
+            };
+            (key, created)
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await
+}
+
+fn lookup<Id: Clone>(
+    resolved: &HashMap<String, Result<Id, Unresolved>>,
+    key: &str,
+) -> Result<Id, Unresolved> {
+    match resolved.get(key) {
+        Some(result) => result.clone(),
+        None => Err(Unresolved::new(format!("{} was never resolved", key))),
+    }
+}
+
+enum RowOutcome {
+    Paid(Transaction),
+    Held(HeldPayment),
+    Skipped(u64), // Done by the run being resumed
+    Cancelled,    // Not started as the import was cancelled
+}
+
+/// A row with the Method ids of everything its payment depends on
+struct ResolvedRow {
+    row_index: u64,
+    position: TextPosition,
+    address_id: Option<u64>,
+    transaction: Transaction,
+}
+
+impl ResolvedRow {
+    /// Records the row as failed, along with whatever it had resolved to
+    async fn fail(
+        &self,
+        pool: &Pool<MySql>,
+        element: &str,
+        reason: String,
+        method_error: Option<String>,
+    ) -> RowFailure {
+        let failure = RowFailure {
+            row_index: self.row_index,
+            position: self.position,
+            raw: RawValues::new(), // Already recorded by the first pass
+            error: ParseError::rejected(element, reason, method_error),
+        };
+        let result = RowResult::failed(
+            Some(&self.transaction),
+            self.address_id,
+            failure.error.to_string(),
+            failure.error.method_error().map(str::to_string),
+        );
+        record_row_result(
+            pool,
+            self.transaction.xml_id.expect("Xml Id was set"),
+            self.row_index,
+            result,
+        )
+        .await;
+        failure
+    }
+}
+
+/// Fills in the ids of everything the row's payment depends on. A row that can't be paid comes
+/// back with the element that failed and whatever it resolved to before that
+fn resolve_row(
+    xml_id: u64,
+    row: ParsedRow,
+    resolved: &ResolvedEntities,
+) -> Result<ResolvedRow, Box<(ResolvedRow, &'static str, Unresolved)>> {
+    let ParsedRow {
+        row_index,
+        position,
+        employee,
+        payor,
+        address,
+        payee,
+        transaction,
+        ..
+    } = row;
+    let mut row = ResolvedRow {
+        row_index,
+        position,
+        address_id: None,
+        transaction,
+    };
+    row.transaction.xml_id = Some(xml_id);
+    row.transaction.row_index = Some(row_index);
+
+    if let Some(address) = address {
+        match lookup(&resolved.addresses, &address_key(&address)) {
+            Ok(id) => row.address_id = Some(id),
+            Err(unresolved) => {
+                return Err(Box::new((
+                    row,
+                    address::Address::XML_IDENTIFIER,
+                    unresolved,
+                )))
+            }
+        }
+    }
+    match lookup(&resolved.employees, &employee.dunkin_id.unwrap()) {
+        Ok(id) => row.transaction.employee_id = Some(id),
+        Err(unresolved) => return Err(Box::new((row, Employee::XML_IDENTIFIER, unresolved))),
+    }
+    match lookup(&resolved.payors, &payor.dunkin_id.unwrap()) {
+        Ok(id) => row.transaction.payor_id = Some(id),
+        Err(unresolved) => return Err(Box::new((row, Payor::XML_IDENTIFIER, unresolved))),
+    }
+    match lookup(&resolved.payees, &payee.plaid_id.unwrap()) {
+        Ok(id) => row.transaction.payee_id = Some(id),
+        Err(unresolved) => return Err(Box::new((row, Payee::XML_IDENTIFIER, unresolved))),
+    }
+
+    Ok(row)
+}
+
+async fn pay_row(
+    pool: &Pool<MySql>,
+    method: &dyn MethodApi,
+    mut row: ResolvedRow,
+) -> Result<RowOutcome, RowFailure> {
+    // The error isn't Send, so only its messages are kept across the await below
+    let created = row
+        .transaction
+        .create(pool, method, ())
+        .await
+        .map_err(|e| (e.to_string(), e.method_message().map(str::to_string)));
+    if let Err((reason, method_error)) = created {
+        return Err(row
+            .fail(pool, Transaction::XML_IDENTIFIER, reason, method_error)
+            .await);
+    }
+
+    info!("Transaction {:?}", row.transaction);
+    let result = RowResult::paid(&row.transaction, row.address_id);
+    record_row_result(
+        pool,
+        row.transaction.xml_id.expect("Xml Id was set"),
+        row.row_index,
+        result,
+    )
+    .await;
+    Ok(RowOutcome::Paid(row.transaction))
+}
+
+async fn hold_row(
+    pool: &Pool<MySql>,
+    row: ResolvedRow,
+    reason: String,
+) -> Result<RowOutcome, RowFailure> {
+    let mut payment = HeldPayment::new(row.row_index, &row.transaction, reason.clone());
+    match payment.insert(pool).await {
+        Ok(id) => {
+            payment.id = Some(id);
+            let result = RowResult::held(&row.transaction, row.address_id, reason);
+            record_row_result(pool, payment.xml_id, row.row_index, result).await;
+            Ok(RowOutcome::Held(payment))
+        }
+        Err(e) => {
+            let reason = format!("Failed to hold likely duplicate due to {}", e);
+            Err(row
+                .fail(pool, Transaction::XML_IDENTIFIER, reason, None)
+                .await)
+        }
+    }
+}
+
+/// Checks the fields the Method requests and the reports rely on, so a bad row is rejected
+/// before anything is created for it
+pub(crate) fn validate_row(row: &ParsedRow) -> Result<(), ParseError> {
+    let required = [
+        (
+            Employee::XML_IDENTIFIER,
+            "DunkinId",
+            row.employee.dunkin_id.is_some(),
+        ),
+        (
+            Employee::XML_IDENTIFIER,
+            "DunkinBranch",
+            row.employee.dunkin_branch.is_some(),
+        ),
+        (
+            Employee::XML_IDENTIFIER,
+            "FirstName",
+            row.employee.first_name.is_some(),
+        ),
+        (
+            Employee::XML_IDENTIFIER,
+            "LastName",
+            row.employee.last_name.is_some(),
+        ),
+        (
+            Payor::XML_IDENTIFIER,
+            "DunkinId",
+            row.payor.dunkin_id.is_some(),
+        ),
+        (
+            Payee::XML_IDENTIFIER,
+            "PlaidId",
+            row.payee.plaid_id.is_some(),
+        ),
+        (
+            Payee::XML_IDENTIFIER,
+            "LoanAccountNumber",
+            row.payee.loan_account_number.is_some(),
+        ),
+    ];
+
+    match required.iter().find(|(_, _, present)| !present) {
+        Some((parent, element, _)) => Err(ParseError::missing(
+            &format!("{}/{}", parent, element),
+            row.position,
+        )),
+        None => Ok(()),
+    }
+}
+
+fn parse_transaction<R: Read>(
+    parser: &mut EventReader<BufReader<R>>,
+    mapping: &FieldMapping,
+    raw: &mut RawValues,
+    row_index: u64,
+    row_position: TextPosition,
+) -> Result<ParsedRow, ParseError> {
     info!("Parsing Transaction");
     let mut transaction = transaction::Transaction::new();
     let mut cur_element = String::from("");
+    // The rest of the row is still consumed after a failure so the next row starts cleanly
+    let mut failure: Option<ParseError> = None;
 
-    let mut payor: Option<Payor> = None;
+    let mut payor: Option<(Payor, Option<address::Address>)> = None;
     let mut employee: Option<Employee> = None;
     let mut payee: Option<Payee> = None;
 
@@ -91,40 +1419,55 @@ async fn parse_transaction<R: Read>(
             Ok(XmlEvent::StartElement { name, .. }) => {
                 cur_element = name.local_name.clone();
                 trace!("Current Element: {}", cur_element);
-                if name.local_name.as_str() == Employee::XML_IDENTIFIER {
-                    employee = Some(parse_employee(parser));
-                }
-                if name.local_name.as_str() == Payor::XML_IDENTIFIER {
-                    payor = Some(parse_payor(parser, pool).await?);
-                }
-                if name.local_name.as_str() == Payee::XML_IDENTIFIER {
-                    payee = Some(parse_payee(parser));
+                let parsed = if name.local_name == mapping.employee {
+                    parse_employee(parser, mapping, raw).map(|e| employee = Some(e))
+                } else if name.local_name == mapping.payor {
+                    parse_payor(parser, mapping, raw).map(|p| payor = Some(p))
+                } else if name.local_name == mapping.payee {
+                    parse_payee(parser, mapping, raw).map(|p| payee = Some(p))
+                } else {
+                    Ok(())
+                };
+                if let Err(e) = parsed {
+                    failure.get_or_insert(e);
                 }
             }
 
             Ok(XmlEvent::EndElement { name, .. }) => {
-                if name.local_name.as_str() == Transaction::XML_IDENTIFIER {
+                if name.local_name == mapping.row {
                     break;
                 }
             }
 
-            Ok(XmlEvent::Characters(text)) => match cur_element.to_lowercase().as_str() {
-                "amount" => {
-                    let raw = text.replace("$", "").parse::<f64>().unwrap() * 100.0;
-                    transaction.amount = Some(raw as u64);
-                }
-                _ => {
-                    error!(
-                        "Transaction: Failed to match identifier '{}' with value '{}'",
-                        cur_element, text
-                    );
+            Ok(XmlEvent::Characters(text)) => {
+                raw.insert(cur_element.clone(), text.clone());
+                let field = cur_element.to_lowercase();
+                match set_transaction_field(&mut transaction, mapping.row_field(&field), &text) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        error!(
+                            "Transaction: Failed to match identifier '{}' with value '{}'",
+                            cur_element, text
+                        );
+                    }
+                    Err(reason) => {
+                        failure.get_or_insert(ParseError::invalid_value(
+                            &cur_element,
+                            &text,
+                            reason,
+                            parser.position(),
+                        ));
+                    }
                 }
-            },
+            }
 
             Ok(XmlEvent::Whitespace(..)) => {}
             Err(e) => {
                 error!("Error: {e}");
-                break;
+                return Err(ParseError::Malformed {
+                    reason: e.msg().to_string(),
+                    position: e.position(),
+                });
             }
             Ok(element) => {
                 warn!("Unexpected Element '{:?}'", element);
@@ -133,29 +1476,38 @@ async fn parse_transaction<R: Read>(
         }
     }
 
-    let mut employee = employee.unwrap();
-    employee.persist(pool, ()).await?;
-    transaction.employee_id = employee.method_id.clone();
-
-    let mut payor = payor.unwrap();
-    payor.persist(pool, ()).await?;
-    transaction.payor_id = payor.method_id;
-
-    let mut payee = payee.unwrap();
-    payee.persist(pool, employee.method_id.unwrap()).await?;
-    transaction.payee_id = payee.method_id;
+    if let Some(e) = failure {
+        return Err(e);
+    }
 
-    transaction.xml_id = Some(xml_id);
-    transaction.persist(pool, ()).await?;
+    let employee = employee.ok_or(ParseError::missing(&mapping.employee, row_position))?;
+    let (payor, address) = payor.ok_or(ParseError::missing(&mapping.payor, row_position))?;
+    let payee = payee.ok_or(ParseError::missing(&mapping.payee, row_position))?;
+    if transaction.amount.is_none() {
+        return Err(ParseError::missing("Amount", row_position));
+    }
 
-    info!("Transaction {:?}", transaction);
-    return Ok(transaction);
+    Ok(ParsedRow {
+        row_index,
+        position: row_position,
+        raw: RawValues::new(), // Filled in by the RowReader, which keeps them for failures too
+        employee,
+        payor,
+        address,
+        payee,
+        transaction,
+    })
 }
 
-fn parse_employee<R: Read>(parser: &mut EventReader<BufReader<R>>) -> Employee {
+fn parse_employee<R: Read>(
+    parser: &mut EventReader<BufReader<R>>,
+    mapping: &FieldMapping,
+    raw: &mut RawValues,
+) -> Result<Employee, ParseError> {
     info!("Parsing Employee");
     let mut employee = Employee::new();
     let mut cur_element = String::from("");
+    let mut failure: Option<ParseError> = None;
     loop {
         match parser.next() {
             Ok(XmlEvent::StartElement { name, .. }) => {
@@ -163,37 +1515,35 @@ fn parse_employee<R: Read>(parser: &mut EventReader<BufReader<R>>) -> Employee {
             }
 
             Ok(XmlEvent::EndElement { name, .. }) => {
-                if name.local_name.as_str() == Employee::XML_IDENTIFIER {
+                if name.local_name == mapping.employee {
                     break;
                 }
             }
 
-            Ok(XmlEvent::Characters(text)) => match cur_element.to_lowercase().as_str() {
-                "dunkinid" => {
-                    employee.dunkin_id = Some(text);
-                }
-                "dunkinbranch" => {
-                    employee.dunkin_branch = Some(text);
-                }
-                "firstname" => {
-                    employee.first_name = Some(text);
-                }
-                "lastname" => {
-                    employee.last_name = Some(text);
-                }
-                "dob" => {
-                    employee.dob = Some(text);
-                }
-                "phonenumber" => {
-                    employee.phone_number = Some(text);
-                }
-                _ => {
-                    error!(
-                        "Employee: Failed to match identifier '{}' with value '{}'",
-                        cur_element, text
-                    );
+            Ok(XmlEvent::Characters(text)) => {
+                raw.insert(
+                    format!("{}/{}", mapping.employee, cur_element),
+                    text.clone(),
+                );
+                let field = cur_element.to_lowercase();
+                match set_employee_field(&mut employee, mapping.employee_field(&field), &text) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        error!(
+                            "Employee: Failed to match identifier '{}' with value '{}'",
+                            cur_element, text
+                        );
+                    }
+                    Err(reason) => {
+                        failure.get_or_insert(ParseError::invalid_value(
+                            &cur_element,
+                            &text,
+                            reason,
+                            parser.position(),
+                        ));
+                    }
                 }
-            },
+            }
 
             Ok(XmlEvent::Whitespace(..)) => {}
             Err(e) => {
@@ -207,13 +1557,21 @@ fn parse_employee<R: Read>(parser: &mut EventReader<BufReader<R>>) -> Employee {
         }
     }
     info!("Finished parsing employee: {:?}", employee);
-    return employee;
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(employee),
+    }
 }
 
-fn parse_payee<R: Read>(parser: &mut EventReader<BufReader<R>>) -> Payee {
+fn parse_payee<R: Read>(
+    parser: &mut EventReader<BufReader<R>>,
+    mapping: &FieldMapping,
+    raw: &mut RawValues,
+) -> Result<Payee, ParseError> {
     info!("Parsing Payee");
     let mut payee = Payee::new();
     let mut cur_element = String::from("");
+    let mut failure: Option<ParseError> = None;
     loop {
         match parser.next() {
             Ok(XmlEvent::StartElement { name, .. }) => {
@@ -221,25 +1579,32 @@ fn parse_payee<R: Read>(parser: &mut EventReader<BufReader<R>>) -> Payee {
             }
 
             Ok(XmlEvent::EndElement { name, .. }) => {
-                if name.local_name.as_str() == Payee::XML_IDENTIFIER {
+                if name.local_name == mapping.payee {
                     break;
                 }
             }
 
-            Ok(XmlEvent::Characters(text)) => match cur_element.to_lowercase().as_str() {
-                "plaidid" => {
-                    payee.plaid_id = Some(text);
-                }
-                "loanaccountnumber" => {
-                    payee.loan_account_number = Some(text.parse::<u64>().unwrap());
-                }
-                _ => {
-                    error!(
-                        "Payee: Failed to match identifier '{}' with value '{}'",
-                        cur_element, text
-                    );
+            Ok(XmlEvent::Characters(text)) => {
+                raw.insert(format!("{}/{}", mapping.payee, cur_element), text.clone());
+                let field = cur_element.to_lowercase();
+                match set_payee_field(&mut payee, mapping.payee_field(&field), &text) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        error!(
+                            "Payee: Failed to match identifier '{}' with value '{}'",
+                            cur_element, text
+                        );
+                    }
+                    Err(reason) => {
+                        failure.get_or_insert(ParseError::invalid_value(
+                            &cur_element,
+                            &text,
+                            reason,
+                            parser.position(),
+                        ));
+                    }
                 }
-            },
+            }
 
             Ok(XmlEvent::Whitespace(..)) => {}
             Err(e) => {
@@ -253,61 +1618,63 @@ fn parse_payee<R: Read>(parser: &mut EventReader<BufReader<R>>) -> Payee {
         }
     }
     info!("Finished parsing payee {:?}", payee);
-    return payee;
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(payee),
+    }
 }
 
-async fn parse_payor<R: Read>(
+fn parse_payor<R: Read>(
     parser: &mut EventReader<BufReader<R>>,
-    pool: &Pool<MySql>,
-) -> Result<Payor, ParseError> {
+    mapping: &FieldMapping,
+    raw: &mut RawValues,
+) -> Result<(Payor, Option<address::Address>), ParseError> {
     info!("Parsing payor");
     let mut payor = Payor::new();
+    let mut address: Option<address::Address> = None;
     let mut cur_element = String::from("");
+    let mut failure: Option<ParseError> = None;
     loop {
         match parser.next() {
             Ok(XmlEvent::StartElement { name, .. }) => {
-                if name.local_name == address::Address::XML_IDENTIFIER.to_string() {
-                    let mut address = parse_address(parser);
-                    address.persist(pool, ()).await?;
-
-                    debug!("Address id {}", address.id.expect("Address Id was set"));
-                    payor.address_id = address.id;
+                if name.local_name == mapping.address {
+                    match parse_address(parser, mapping, raw) {
+                        Ok(parsed) => address = Some(parsed),
+                        Err(e) => {
+                            failure.get_or_insert(e);
+                        }
+                    }
                 }
                 cur_element = name.local_name;
             }
 
             Ok(XmlEvent::EndElement { name, .. }) => {
-                if name.local_name.as_str() == Payor::XML_IDENTIFIER {
+                if name.local_name == mapping.payor {
                     break;
                 }
             }
 
-            Ok(XmlEvent::Characters(text)) => match cur_element.to_lowercase().as_str() {
-                "dunkinid" => {
-                    payor.dunkin_id = Some(text);
-                }
-                "name" => {
-                    payor.payor_name = Some(text);
-                }
-                "dba" => {
-                    payor.dba = Some(text);
-                }
-                "ein" => {
-                    payor.ein = Some(text);
-                }
-                "accountnumber" => {
-                    payor.account_number = Some(text.parse::<u64>().unwrap());
-                }
-                "abarouting" => {
-                    payor.aba_routing = Some(text.parse::<u64>().unwrap());
-                }
-                _ => {
-                    error!(
-                        "Payor: Failed to match identifier '{}' with value '{}'",
-                        cur_element, text
-                    );
+            Ok(XmlEvent::Characters(text)) => {
+                raw.insert(format!("{}/{}", mapping.payor, cur_element), text.clone());
+                let field = cur_element.to_lowercase();
+                match set_payor_field(&mut payor, mapping.payor_field(&field), &text) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        error!(
+                            "Payor: Failed to match identifier '{}' with value '{}'",
+                            cur_element, text
+                        );
+                    }
+                    Err(reason) => {
+                        failure.get_or_insert(ParseError::invalid_value(
+                            &cur_element,
+                            &text,
+                            reason,
+                            parser.position(),
+                        ));
+                    }
                 }
-            },
+            }
 
             Ok(XmlEvent::Whitespace(..)) => {}
             Err(e) => {
@@ -321,13 +1688,21 @@ async fn parse_payor<R: Read>(
         }
     }
     info!("Finished parsing payor {:?}", payor);
-    return Ok(payor);
+    match failure {
+        Some(e) => Err(e),
+        None => Ok((payor, address)),
+    }
 }
 
-fn parse_address<R: Read>(parser: &mut EventReader<BufReader<R>>) -> address::Address {
+fn parse_address<R: Read>(
+    parser: &mut EventReader<BufReader<R>>,
+    mapping: &FieldMapping,
+    raw: &mut RawValues,
+) -> Result<address::Address, ParseError> {
     info!("Parsing address");
     let mut address = address::Address::new();
     let mut cur_element = String::from("");
+    let mut failure: Option<ParseError> = None;
     loop {
         match parser.next() {
             Ok(XmlEvent::StartElement { name, .. }) => {
@@ -335,31 +1710,35 @@ fn parse_address<R: Read>(parser: &mut EventReader<BufReader<R>>) -> address::Ad
             }
 
             Ok(XmlEvent::EndElement { name, .. }) => {
-                if name.local_name.as_str() == address::Address::XML_IDENTIFIER {
+                if name.local_name == mapping.address {
                     break;
                 }
             }
 
-            Ok(XmlEvent::Characters(text)) => match cur_element.to_lowercase().as_str() {
-                "line1" => {
-                    address.line1 = Some(text);
-                }
-                "city" => {
-                    address.city = Some(text);
-                }
-                "state" => {
-                    address.state = Some(text);
-                }
-                "zip" => {
-                    address.zip = Some(text.parse::<u64>().unwrap());
-                }
-                _ => {
-                    error!(
-                        "Address: Failed to match identifier '{}' with value '{}'",
-                        cur_element, text
-                    );
+            Ok(XmlEvent::Characters(text)) => {
+                raw.insert(
+                    format!("{}/{}/{}", mapping.payor, mapping.address, cur_element),
+                    text.clone(),
+                );
+                let field = cur_element.to_lowercase();
+                match set_address_field(&mut address, mapping.address_field(&field), &text) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        error!(
+                            "Address: Failed to match identifier '{}' with value '{}'",
+                            cur_element, text
+                        );
+                    }
+                    Err(reason) => {
+                        failure.get_or_insert(ParseError::invalid_value(
+                            &cur_element,
+                            &text,
+                            reason,
+                            parser.position(),
+                        ));
+                    }
                 }
-            },
+            }
 
             Ok(XmlEvent::Whitespace(..)) => {}
             Err(e) => {
@@ -373,5 +1752,327 @@ fn parse_address<R: Read>(parser: &mut EventReader<BufReader<R>>) -> address::Ad
         }
     }
     info!("Finished parsing address {:?}", address);
-    return address;
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(address),
+    }
+}
+
+/// Field setters shared by every input format, `field` is the lowercased element or column
+/// name. Returns whether `field` belongs to the entity, or why `text` is not a valid value for it
+pub(crate) fn set_transaction_field(
+    transaction: &mut Transaction,
+    field: &str,
+    text: &str,
+) -> Result<bool, String> {
+    match field {
+        "amount" => {
+            transaction.amount = Some(Money::parse_amount(text).map_err(|e| e.to_string())?)
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+pub(crate) fn set_employee_field(
+    employee: &mut Employee,
+    field: &str,
+    text: &str,
+) -> Result<bool, String> {
+    match field {
+        "dunkinid" => employee.dunkin_id = Some(text.to_string()),
+        "dunkinbranch" => employee.dunkin_branch = Some(text.to_string()),
+        "firstname" => employee.first_name = Some(text.to_string()),
+        "lastname" => employee.last_name = Some(text.to_string()),
+        "dob" => employee.dob = Some(text.to_string()),
+        "phonenumber" => employee.phone_number = Some(text.to_string()),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+pub(crate) fn set_payee_field(payee: &mut Payee, field: &str, text: &str) -> Result<bool, String> {
+    match field {
+        "plaidid" => payee.plaid_id = Some(text.to_string()),
+        "loanaccountnumber" => {
+            payee.loan_account_number = Some(text.parse::<u64>().map_err(|e| e.to_string())?)
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+pub(crate) fn set_payor_field(payor: &mut Payor, field: &str, text: &str) -> Result<bool, String> {
+    match field {
+        "dunkinid" => payor.dunkin_id = Some(text.to_string()),
+        "name" => payor.payor_name = Some(text.to_string()),
+        "dba" => payor.dba = Some(text.to_string()),
+        "ein" => payor.ein = Some(text.to_string()),
+        "accountnumber" => {
+            payor.account_number = Some(text.parse::<u64>().map_err(|e| e.to_string())?)
+        }
+        "abarouting" => payor.aba_routing = Some(text.parse::<u64>().map_err(|e| e.to_string())?),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+pub(crate) fn set_address_field(
+    address: &mut address::Address,
+    field: &str,
+    text: &str,
+) -> Result<bool, String> {
+    match field {
+        "line1" => address.line1 = Some(text.to_string()),
+        "city" => address.city = Some(text.to_string()),
+        "state" => address.state = Some(text.to_string()),
+        "zip" => address.zip = Some(text.parse::<u64>().map_err(|e| e.to_string())?),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(employee: &str, amount: &str) -> String {
+        format!(
+            "<row><Employee><DunkinId>{}</DunkinId><DunkinBranch>BRC1</DunkinBranch>\
+            <FirstName>Ada</FirstName><LastName>Lovelace</LastName></Employee>\
+            <Payor><DunkinId>CORP1</DunkinId></Payor>\
+            <Payee><PlaidId>ins_1</PlaidId><LoanAccountNumber>12345</LoanAccountNumber></Payee>\
+            <Amount>{}</Amount></row>",
+            employee, amount
+        )
+    }
+
+    fn rows(body: &str) -> Vec<Result<ParsedRow, RowFailure>> {
+        RowReader::new(body.as_bytes(), FieldMapping::default()).collect()
+    }
+
+    #[test]
+    fn numbers_rows_in_file_order_with_their_lines() {
+        let body = format!(
+            "<root>\n{}\n\n{}\n</root>",
+            row("EMP1", "$1.00"),
+            row("EMP2", "$2.50")
+        );
+        let rows = rows(&body);
+        assert_eq!(rows.len(), 2);
+
+        let first = rows[0].as_ref().unwrap();
+        assert_eq!(first.row_index, 1);
+        assert_eq!(first.position.row, 1);
+        assert_eq!(first.employee.dunkin_id.as_deref(), Some("EMP1"));
+        assert_eq!(
+            first.raw.get("Payor/DunkinId").map(String::as_str),
+            Some("CORP1")
+        );
+
+        let second = rows[1].as_ref().unwrap();
+        assert_eq!(second.row_index, 2);
+        assert_eq!(second.position.row, 3);
+        assert_eq!(second.transaction.amount, Some(Money::from_cents(250)));
+    }
+
+    #[test]
+    fn an_invalid_row_fails_alone() {
+        let body = format!(
+            "<root>\n{}\n{}\n{}\n</root>",
+            row("EMP1", "$1.00"),
+            row("EMP2", "lots"),
+            row("EMP3", "$3.00")
+        );
+        let rows = rows(&body);
+        assert_eq!(rows.len(), 3);
+        assert!(rows[0].is_ok());
+        assert_eq!(rows[2].as_ref().unwrap().row_index, 3);
+
+        let failure = rows[1].as_ref().unwrap_err();
+        assert_eq!(failure.row_index, 2);
+        assert!(matches!(
+            &failure.error,
+            ParseError::InvalidValue { element, value, .. } if element == "Amount" && value == "lots"
+        ));
+        assert_eq!(failure.raw.get("Amount").map(String::as_str), Some("lots"));
+        let error = failure.to_row_error(Some(7));
+        assert_eq!(error.row_index, 2);
+        assert_eq!(error.line, 3);
+        assert_eq!(error.element, "Amount");
+    }
+
+    #[test]
+    fn a_row_missing_a_required_element_fails() {
+        let body = format!(
+            "<root>{}{}</root>",
+            row("EMP1", "$1.00").replace("<PlaidId>ins_1</PlaidId>", ""),
+            row("EMP2", "$2.00")
+        );
+        let rows = rows(&body);
+        assert!(matches!(
+            &rows[0].as_ref().unwrap_err().error,
+            ParseError::MissingElement { element, .. } if element == "Payee/PlaidId"
+        ));
+        assert_eq!(rows[1].as_ref().unwrap().row_index, 2);
+    }
+
+    #[test]
+    fn malformed_xml_between_rows_fails_the_file() {
+        let body = format!(
+            "<root>\n{}\n{}\n</rot>\n{}",
+            row("EMP1", "$1.00"),
+            row("EMP2", "$2.00"),
+            row("EMP3", "$3.00")
+        );
+        let rows = rows(&body);
+        assert_eq!(rows.len(), 3);
+        assert!(rows[0].is_ok() && rows[1].is_ok());
+
+        let failure = rows[2].as_ref().unwrap_err();
+        assert_eq!(failure.row_index, 0);
+        assert!(matches!(failure.error, ParseError::Malformed { .. }));
+        assert_eq!(failure.to_row_error(None).line, 4);
+    }
+
+    #[test]
+    fn malformed_xml_in_a_row_fails_it_and_stops() {
+        let body = format!(
+            "<root>{}{}{}</root>",
+            row("EMP1", "$1.00"),
+            row("EMP2", "$2.00").replace("</Payor>", "</Payee>"),
+            row("EMP3", "$3.00")
+        );
+        let rows = rows(&body);
+        assert_eq!(rows.len(), 2);
+        let failure = rows[1].as_ref().unwrap_err();
+        assert_eq!(failure.row_index, 2);
+        assert!(matches!(failure.error, ParseError::Malformed { .. }));
+    }
+
+    fn recorded(row_index: u64, status: &str) -> TransactionRow {
+        TransactionRow {
+            status: String::from(status),
+            ..TransactionRow::new(1, row_index, String::from("{}"), None)
+        }
+    }
+
+    #[test]
+    fn new_imports_have_nothing_done() {
+        let progress = Progress::new(None, &[], []);
+        assert!(!progress.resuming);
+        assert_eq!(progress.checkpoint, 0);
+        assert!(!progress.is_done(1));
+    }
+
+    #[test]
+    fn rows_up_to_the_checkpoint_are_done() {
+        let progress = Progress::new(Some(100), &[], []);
+        assert!(progress.resuming);
+        assert!(progress.is_done(1));
+        assert!(progress.is_done(100));
+        assert!(!progress.is_done(101));
+    }
+
+    #[test]
+    fn rows_past_the_checkpoint_are_done_once_processed() {
+        let rows = [
+            recorded(101, TransactionRow::PAID),
+            recorded(102, TransactionRow::PARSED),
+            recorded(103, TransactionRow::FAILED),
+            recorded(104, TransactionRow::HELD),
+            recorded(105, TransactionRow::PARSED),
+        ];
+        // Row 105 was paid, but the server stopped before its row was updated
+        let progress = Progress::new(Some(100), &rows, [101, 104, 105]);
+        assert!(progress.is_done(101));
+        assert!(!progress.is_done(102));
+        assert!(progress.is_done(103));
+        assert!(progress.is_done(104));
+        assert!(progress.is_done(105));
+        assert!(!progress.is_done(106));
+
+        assert_eq!(progress.recorded.len(), 5);
+        assert!(!progress.settled.contains(&103));
+        assert!(progress.settled.contains(&105));
+    }
+
+    #[test]
+    fn recorded_rows_resume_without_a_checkpoint() {
+        let rows = [recorded(1, TransactionRow::PARSED)];
+        let progress = Progress::new(None, &rows, []);
+        assert!(progress.resuming);
+        assert!(!progress.is_done(1));
+    }
+
+    #[test]
+    fn checkpoints_every_interval() {
+        let mut checkpoints = Checkpoints::new(0);
+        for row_index in 1..CHECKPOINT_INTERVAL {
+            assert_eq!(checkpoints.processed(row_index), None);
+        }
+        assert_eq!(
+            checkpoints.processed(CHECKPOINT_INTERVAL),
+            Some(CHECKPOINT_INTERVAL)
+        );
+        assert_eq!(checkpoints.processed(CHECKPOINT_INTERVAL + 1), None);
+        assert_eq!(checkpoints.finish(), Some(CHECKPOINT_INTERVAL + 1));
+    }
+
+    #[test]
+    fn checkpoints_skip_rows() {
+        // Rows that failed to read never come back, the checkpoint moves past them all the same
+        let mut checkpoints = Checkpoints::new(0);
+        for row_index in 1..CHECKPOINT_INTERVAL {
+            checkpoints.processed(row_index * 2);
+        }
+        assert_eq!(
+            checkpoints.processed(CHECKPOINT_INTERVAL * 2),
+            Some(CHECKPOINT_INTERVAL * 2)
+        );
+    }
+
+    #[test]
+    fn checkpoints_never_move_back() {
+        let mut checkpoints = Checkpoints::new(CHECKPOINT_INTERVAL * 2);
+        for row_index in 1..=CHECKPOINT_INTERVAL {
+            assert_eq!(checkpoints.processed(row_index), None);
+        }
+        assert_eq!(checkpoints.finish(), None);
+        assert_eq!(Checkpoints::new(5).finish(), None);
+    }
+
+    #[test]
+    fn checkpoints_once_finished_if_moved() {
+        let mut checkpoints = Checkpoints::new(0);
+        checkpoints.processed(1);
+        checkpoints.processed(2);
+        assert_eq!(checkpoints.finish(), Some(2));
+    }
+
+    #[test]
+    fn cancelled_imports_start_no_more_rows() {
+        let rows = [recorded(3, TransactionRow::PAID)];
+        let progress = Progress::new(Some(1), &rows, [3]);
+        assert_eq!(progress.start(2, false), RowStart::Run);
+        assert_eq!(progress.start(2, true), RowStart::Cancel);
+        assert_eq!(progress.start(4, true), RowStart::Cancel);
+    }
+
+    #[test]
+    fn cancelled_imports_still_skip_rows_done_before() {
+        let rows = [recorded(3, TransactionRow::PAID)];
+        let progress = Progress::new(Some(1), &rows, [3]);
+        assert_eq!(progress.start(1, true), RowStart::Skip);
+        assert_eq!(progress.start(3, true), RowStart::Skip);
+        assert_eq!(progress.start(3, false), RowStart::Skip);
+    }
+
+    #[test]
+    fn cancelled_errors_report_rows_paid() {
+        let error = ParseError::Cancelled { paid: 37 };
+        assert_eq!(error.to_string(), "Cancelled after 37 rows were paid");
+        assert_eq!(error.element(), None);
+        assert!(error.position().is_none());
+    }
 }
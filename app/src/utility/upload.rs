@@ -0,0 +1,128 @@
+use axum::body::Bytes;
+use axum::extract::multipart::MultipartError;
+use futures_util::{Stream, StreamExt};
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::task;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Multipart Error: {0}")]
+    MultipartError(#[source] MultipartError),
+    #[error("Body Error: {0}")]
+    BodyError(#[source] axum::Error),
+    #[error("IO Error: {0}")]
+    IOError(#[source] std::io::Error),
+    #[error("Task Error: {0}")]
+    TaskError(#[source] task::JoinError),
+}
+
+impl From<axum::Error> for Error {
+    fn from(value: axum::Error) -> Self {
+        Error::BodyError(value)
+    }
+}
+
+impl From<MultipartError> for Error {
+    fn from(value: MultipartError) -> Self {
+        Error::MultipartError(value)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::IOError(value)
+    }
+}
+
+/// Directory uploads are spooled into before being parsed
+pub fn upload_dir() -> PathBuf {
+    match env::var("UPLOAD_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => env::temp_dir().join("method-uploads"),
+    }
+}
+
+pub fn upload_path(xml_id: u64) -> PathBuf {
+    upload_dir().join(format!("{}.upload", xml_id))
+}
+
+/// A new file in [upload_dir] that is deleted when dropped, unless it is moved to its
+/// [upload_path] with [keep]
+pub async fn spool_file() -> Result<NamedTempFile, Error> {
+    let dir = upload_dir();
+    fs::create_dir_all(&dir).await?;
+    Ok(NamedTempFile::new_in(dir)?)
+}
+
+pub fn keep(file: NamedTempFile, xml_id: u64) -> Result<PathBuf, Error> {
+    let path = upload_path(xml_id);
+    file.persist(&path).map_err(|e| e.error)?;
+    Ok(path)
+}
+
+#[derive(Debug, Clone)]
+pub struct Spooled {
+    pub size: u64,
+    /// Hex encoded SHA-256 of the contents
+    pub content_hash: String,
+}
+
+/// Writes a multipart field or request body to `path` a chunk at a time so memory use does not
+/// grow with the file size, hashing it along the way
+pub async fn spool<S, E>(mut body: S, path: &Path) -> Result<Spooled, Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    Error: From<E>,
+{
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    let mut file = fs::File::create(path).await?;
+    let mut hasher = Sha256::new();
+    let mut written: u64 = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        hasher.update(&chunk);
+        written += chunk.len() as u64;
+    }
+    file.flush().await?;
+
+    debug!("Spooled {} bytes to {}", written, path.display());
+    Ok(Spooled {
+        size: written,
+        content_hash: hex::encode(hasher.finalize()),
+    })
+}
+
+/// Copies a local file into [upload_dir] and hashes it the way [spool] does, for files that
+/// were never uploaded
+pub async fn spool_local(source: &Path) -> Result<(NamedTempFile, Spooled), Error> {
+    let file = spool_file().await?;
+    let size = fs::copy(source, file.path()).await?;
+    let path = file.path().to_path_buf();
+    let content_hash = task::spawn_blocking(move || hash_file(&path))
+        .await
+        .map_err(Error::TaskError)??;
+    Ok((file, Spooled { size, content_hash }))
+}
+
+/// Hashes a file the same way [spool] does, for files that were never uploaded
+pub fn hash_file(path: &Path) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub async fn remove(path: &Path) {
+    if let Err(e) = fs::remove_file(path).await {
+        warn!("Failed to remove upload {} due to {}", path.display(), e);
+    }
+}
@@ -0,0 +1,470 @@
+use lazy_static::lazy_static;
+use log::{error, info};
+use std::io::{BufReader, Read};
+use xml::attribute::OwnedAttribute;
+use xml::common::{Position, TextPosition};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::utility::parser::{ParseError, RawValues, RowFailure};
+
+const XS_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema";
+
+/// Past this many the file is clearly the wrong layout, and the rest would only bloat the report
+const MAX_VIOLATIONS: usize = 1000;
+
+lazy_static! {
+    /// The schema published for payroll uploads, shipped in `xsd/payroll.xsd`
+    pub static ref PAYROLL_SCHEMA: Schema =
+        Schema::parse(PAYROLL_XSD).expect("xsd/payroll.xsd is a valid schema");
+}
+
+pub const PAYROLL_XSD: &str = include_str!("../../xsd/payroll.xsd");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SimpleType {
+    String,
+    UnsignedLong,
+}
+
+#[derive(Debug, Clone)]
+struct ElementRule {
+    name: String,
+    min_occurs: u64,
+    max_occurs: Option<u64>, // None for unbounded
+    simple_type: SimpleType,
+    children: Option<Vec<ElementRule>>, // None for elements holding text
+    ordered: bool,                      // xs:sequence rather than xs:all
+}
+
+/// The subset of XSD the payroll schema is written in: nested anonymous complex types made of
+/// `xs:sequence` or `xs:all`, `minOccurs`/`maxOccurs`, and `xs:string`/`xs:unsignedLong` leaves
+#[derive(Debug, Clone)]
+pub struct Schema {
+    root: ElementRule,
+}
+
+impl Schema {
+    pub fn parse(xsd: &str) -> Result<Self, String> {
+        let mut parser = EventReader::new(xsd.as_bytes());
+        let mut open: Vec<ElementRule> = vec![];
+        let mut root: Option<ElementRule> = None;
+
+        loop {
+            match parser.next().map_err(|e| e.to_string())? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    if name.namespace.as_deref() != Some(XS_NAMESPACE) {
+                        return Err(format!("Unexpected element {} in schema", name.local_name));
+                    }
+                    match name.local_name.as_str() {
+                        "schema" | "annotation" | "documentation" => {}
+                        "element" => open.push(ElementRule::from_attributes(&attributes)?),
+                        "complexType" => match open.last_mut() {
+                            Some(rule) => rule.children = Some(vec![]),
+                            None => return Err(String::from("complexType outside an element")),
+                        },
+                        "sequence" | "all" => match open.last_mut() {
+                            Some(rule) => rule.ordered = name.local_name == "sequence",
+                            None => return Err(format!("{} outside an element", name.local_name)),
+                        },
+                        other => return Err(format!("xs:{} is not supported", other)),
+                    }
+                }
+                XmlEvent::EndElement { name } if name.local_name == "element" => {
+                    let rule = open.pop().ok_or("Unbalanced xs:element")?;
+                    match open.last_mut() {
+                        Some(parent) => parent.children.get_or_insert_with(Vec::new).push(rule),
+                        None => root = Some(rule),
+                    }
+                }
+                XmlEvent::EndDocument => break,
+                _ => {}
+            }
+        }
+
+        match root {
+            Some(root) => Ok(Self { root }),
+            None => Err(String::from("Schema does not declare a root element")),
+        }
+    }
+
+    /// Checks the whole document against the schema without parsing any rows. Each violation is
+    /// reported against the `<row>` it was found in, or row 0 outside of one
+    pub fn check<R: Read>(&self, reader: R) -> Vec<RowFailure> {
+        let mut parser = EventReader::new(BufReader::new(reader));
+        let mut checker = Checker {
+            schema: self,
+            stack: vec![],
+            row_index: 0,
+            violations: vec![],
+        };
+
+        while checker.violations.len() < MAX_VIOLATIONS {
+            match parser.next() {
+                Ok(XmlEvent::StartElement { name, .. }) => {
+                    checker.start(&name.local_name, parser.position());
+                }
+                Ok(XmlEvent::EndElement { .. }) => checker.end(),
+                Ok(XmlEvent::Characters(text)) | Ok(XmlEvent::CData(text)) => {
+                    checker.text(&text, parser.position());
+                }
+                Ok(XmlEvent::EndDocument) => {
+                    info!("End of document");
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    error!("Error checking document due to {e}");
+                    checker.violations.push(RowFailure {
+                        row_index: checker.row_index,
+                        position: e.position(),
+                        raw: RawValues::new(),
+                        error: ParseError::Malformed {
+                            reason: e.msg().to_string(),
+                            position: e.position(),
+                        },
+                    });
+                    break;
+                }
+            }
+        }
+        checker.violations
+    }
+}
+
+impl ElementRule {
+    fn from_attributes(attributes: &[OwnedAttribute]) -> Result<Self, String> {
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|a| a.name.local_name == name)
+                .map(|a| a.value.as_str())
+        };
+        let occurs = |name: &str| -> Result<Option<u64>, String> {
+            match attribute(name) {
+                None => Ok(Some(1)),
+                Some("unbounded") => Ok(None),
+                Some(value) => value
+                    .parse::<u64>()
+                    .map(Some)
+                    .map_err(|e| format!("Invalid {} '{}': {}", name, value, e)),
+            }
+        };
+
+        let name = attribute("name").ok_or("xs:element without a name")?;
+        let simple_type = match attribute("type") {
+            None | Some("xs:string") => SimpleType::String,
+            Some("xs:unsignedLong") => SimpleType::UnsignedLong,
+            Some(other) => return Err(format!("Type {} of {} is not supported", other, name)),
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            min_occurs: occurs("minOccurs")?.ok_or("minOccurs can't be unbounded")?,
+            max_occurs: occurs("maxOccurs")?,
+            simple_type,
+            children: None,
+            ordered: false,
+        })
+    }
+}
+
+/// An element of the document being checked, `rule` is None for elements that were already
+/// reported as unknown, whose contents are skipped
+struct Frame<'a> {
+    rule: Option<&'a ElementRule>,
+    position: TextPosition,
+    counts: Vec<u64>,
+    last_child: usize,
+}
+
+struct Checker<'a> {
+    schema: &'a Schema,
+    stack: Vec<Frame<'a>>,
+    row_index: u64,
+    violations: Vec<RowFailure>,
+}
+
+impl<'a> Checker<'a> {
+    fn violation(&mut self, element: &str, reason: String, position: TextPosition) {
+        self.violations.push(RowFailure {
+            row_index: self.row_index,
+            position,
+            raw: RawValues::new(),
+            error: ParseError::SchemaViolation {
+                element: element.to_string(),
+                reason,
+                position,
+            },
+        });
+    }
+
+    fn start(&mut self, name: &str, position: TextPosition) {
+        if self.stack.len() == 1 {
+            self.row_index += 1;
+        }
+
+        let rule = match self.stack.last_mut() {
+            None if name == self.schema.root.name => Some(&self.schema.root),
+            None => {
+                let reason = format!("Document must start with <{}>", self.schema.root.name);
+                self.violation(name, reason, position);
+                None
+            }
+            Some(Frame { rule: None, .. }) => None,
+            Some(frame) => {
+                let parent = frame.rule.expect("Checked above");
+                let children = parent.children.as_deref().unwrap_or(&[]);
+                match children.iter().position(|child| child.name == name) {
+                    Some(index) => {
+                        frame.counts[index] += 1;
+                        let out_of_order = parent.ordered && index < frame.last_child;
+                        frame.last_child = index;
+                        let child = &children[index];
+                        let count = frame.counts[index];
+
+                        if out_of_order {
+                            let reason =
+                                format!("<{}> is out of order within <{}>", name, parent.name);
+                            self.violation(name, reason, position);
+                        }
+                        if child.max_occurs.is_some_and(|max| count > max) {
+                            let reason = format!(
+                                "<{}> appears more than {} time(s) within <{}>",
+                                name,
+                                child.max_occurs.unwrap_or_default(),
+                                parent.name
+                            );
+                            self.violation(name, reason, position);
+                        }
+                        Some(child)
+                    }
+                    None => {
+                        let reason = format!("<{}> is not allowed within <{}>", name, parent.name);
+                        self.violation(name, reason, position);
+                        None
+                    }
+                }
+            }
+        };
+
+        let counts = vec![0; rule.and_then(|r| r.children.as_ref()).map_or(0, Vec::len)];
+        self.stack.push(Frame {
+            rule,
+            position,
+            counts,
+            last_child: 0,
+        });
+    }
+
+    fn end(&mut self) {
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let rule = match frame.rule {
+            Some(rule) => rule,
+            None => return,
+        };
+
+        let children = rule.children.as_deref().unwrap_or(&[]);
+        for (child, count) in children.iter().zip(frame.counts) {
+            if count < child.min_occurs {
+                let reason = format!("<{}> is missing required <{}>", rule.name, child.name);
+                self.violation(&child.name, reason, frame.position);
+            }
+        }
+    }
+
+    fn text(&mut self, text: &str, position: TextPosition) {
+        let rule = match self.stack.last() {
+            Some(Frame {
+                rule: Some(rule), ..
+            }) => *rule,
+            _ => return,
+        };
+
+        if rule.children.is_some() {
+            let reason = format!("<{}> can only contain elements", rule.name);
+            self.violation(&rule.name, reason, position);
+        } else if rule.simple_type == SimpleType::UnsignedLong {
+            if let Err(e) = text.trim().parse::<u64>() {
+                let reason = format!("'{}' is not an unsignedLong: {}", text, e);
+                self.violation(&rule.name, reason, position);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XSD: &str = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+  <xs:element name="root">
+    <xs:complexType>
+      <xs:sequence>
+        <xs:element name="row" minOccurs="0" maxOccurs="unbounded">
+          <xs:complexType>
+            <xs:sequence>
+              <xs:element name="Id" type="xs:unsignedLong"/>
+              <xs:element name="Name" type="xs:string" minOccurs="0" maxOccurs="2"/>
+              <xs:element name="Note" minOccurs="0"/>
+            </xs:sequence>
+          </xs:complexType>
+        </xs:element>
+      </xs:sequence>
+    </xs:complexType>
+  </xs:element>
+</xs:schema>"#;
+
+    /// The row, line and reason of every violation in `document`
+    fn check(document: &str) -> Vec<(u64, u64, String)> {
+        Schema::parse(XSD)
+            .unwrap()
+            .check(document.as_bytes())
+            .iter()
+            .map(|violation| {
+                let error = violation.to_row_error(None);
+                (error.row_index, error.line, violation.error.to_string())
+            })
+            .collect()
+    }
+
+    fn reason(reason: &str) -> String {
+        format!("Schema violation: {}", reason)
+    }
+
+    #[test]
+    fn accepts_documents_that_follow_the_schema() {
+        assert!(check("<root/>").is_empty());
+        assert!(check(
+            "<root><row><Id>1</Id></row><row><Id> 2 </Id><Name>a</Name><Name>b</Name>\
+             <Note>n</Note></row></root>"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn the_published_schema_accepts_the_example_file() {
+        let violations = PAYROLL_SCHEMA.check(include_str!("../../file.xml").as_bytes());
+        assert!(violations.is_empty(), "{:?}", violations);
+    }
+
+    #[test]
+    fn sequences_are_in_order_but_all_is_any_order() {
+        assert_eq!(
+            check("<root><row><Name>a</Name><Id>1</Id></row></root>"),
+            vec![(1, 1, reason("<Id> is out of order within <row>"))]
+        );
+
+        let row = "<row><Amount>$1.00</Amount>\
+            <Payee><LoanAccountNumber>1</LoanAccountNumber><PlaidId>ins_1</PlaidId></Payee>\
+            <Payor><DunkinId>CORP1</DunkinId></Payor>\
+            <Employee><LastName>L</LastName><FirstName>F</FirstName>\
+            <DunkinBranch>B</DunkinBranch><DunkinId>EMP1</DunkinId></Employee></row>";
+        let violations = PAYROLL_SCHEMA.check(format!("<root>{}</root>", row).as_bytes());
+        assert!(violations.is_empty(), "{:?}", violations);
+    }
+
+    #[test]
+    fn checks_min_and_max_occurs() {
+        assert_eq!(
+            check("<root><row><Name>a</Name></row></root>"),
+            vec![(1, 1, reason("<row> is missing required <Id>"))]
+        );
+        assert_eq!(
+            check("<root><row><Id>1</Id><Name>a</Name><Name>b</Name><Name>c</Name></row></root>"),
+            vec![(
+                1,
+                1,
+                reason("<Name> appears more than 2 time(s) within <row>")
+            )]
+        );
+    }
+
+    #[test]
+    fn checks_simple_types() {
+        assert_eq!(
+            check("<root><row><Id>12a</Id></row></root>"),
+            vec![(
+                1,
+                1,
+                reason("'12a' is not an unsignedLong: invalid digit found in string")
+            )]
+        );
+        assert_eq!(
+            check("<root><row>text<Id>1</Id></row></root>"),
+            vec![(1, 1, reason("<row> can only contain elements"))]
+        );
+    }
+
+    #[test]
+    fn reports_unknown_elements_once_without_their_contents() {
+        assert_eq!(
+            check("<root><row><Id>1</Id><Extra><Deeper>x</Deeper></Extra></row></root>"),
+            vec![(1, 1, reason("<Extra> is not allowed within <row>"))]
+        );
+        assert_eq!(
+            check("<other><row/></other>"),
+            vec![(0, 1, reason("Document must start with <root>"))]
+        );
+    }
+
+    #[test]
+    fn reports_violations_against_their_row_and_line() {
+        let document = "<root>\n<row>\n<Id>1</Id>\n</row>\n<row>\n<Id>1</Id>\n<Note>a</Note>\n\
+                        <Name>b</Name>\n</row>\n<row>\n</row>\n</root>";
+        assert_eq!(
+            check(document),
+            vec![
+                (2, 8, reason("<Name> is out of order within <row>")),
+                (3, 10, reason("<row> is missing required <Id>")),
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_at_malformed_xml() {
+        let violations = Schema::parse(XSD)
+            .unwrap()
+            .check("<root><row><Id>1</Id></rows></root>".as_bytes());
+        assert_eq!(violations.len(), 1);
+        assert!(matches!(violations[0].error, ParseError::Malformed { .. }));
+    }
+
+    #[test]
+    fn rejects_schemas_using_unsupported_constructs() {
+        let schema = |body: &str| {
+            Schema::parse(&format!(
+                r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">{}</xs:schema>"#,
+                body
+            ))
+        };
+        assert_eq!(
+            schema(
+                r#"<xs:element name="a"><xs:complexType><xs:choice/></xs:complexType></xs:element>"#
+            )
+            .unwrap_err(),
+            "xs:choice is not supported"
+        );
+        assert_eq!(
+            schema(r#"<xs:element name="a" type="xs:date"/>"#).unwrap_err(),
+            "Type xs:date of a is not supported"
+        );
+        assert_eq!(
+            schema(r#"<xs:element name="a" minOccurs="unbounded"/>"#).unwrap_err(),
+            "minOccurs can't be unbounded"
+        );
+        assert_eq!(
+            schema(r#"<xs:element type="xs:string"/>"#).unwrap_err(),
+            "xs:element without a name"
+        );
+        assert_eq!(
+            schema("").unwrap_err(),
+            "Schema does not declare a root element"
+        );
+        assert!(schema(r#"<element name="a"/>"#).is_err());
+    }
+}
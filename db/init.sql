@@ -4,7 +4,18 @@ CREATE TABLE IF NOT EXISTS XmlParse (
     Filename VARCHAR(255),
     StartedAt VARCHAR(255),
     FinishedAt VARCHAR(255),
-    PRIMARY KEY(Id)
+    ContentHash CHAR(64),
+    DuplicateOf INT UNSIGNED,
+    OverrideReason TEXT,
+    Format VARCHAR(32),
+    Profile VARCHAR(255),
+    Checkpoint INT UNSIGNED,
+    -- Only one import of the same content may be live, duplicates and failures don't count
+    ActiveContentHash CHAR(64) AS (IF(DuplicateOf IS NULL AND Status <> 'Failed', ContentHash, NULL)) STORED,
+    PRIMARY KEY(Id),
+    INDEX (ContentHash),
+    UNIQUE (ActiveContentHash),
+    FOREIGN KEY (DuplicateOf) REFERENCES XmlParse(Id)
 );
 
 CREATE TABLE IF NOT EXISTS Employees (
@@ -51,9 +62,94 @@ CREATE TABLE IF NOT EXISTS Transactions (
     PayeeId VARCHAR(255),
     XmlId INT UNSIGNED,
     Amount INT UNSIGNED,
+    RowIndex INT UNSIGNED,
+    CreatedAt TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     PRIMARY KEY(MethodId),
+    INDEX (EmployeeId, CreatedAt),
     FOREIGN KEY (EmployeeId) REFERENCES Employees(MethodId),
     FOREIGN KEY (PayorId) REFERENCES Payors(MethodId),
     FOREIGN KEY (PayeeId) REFERENCES Payees(MethodId),
     FOREIGN KEY (XmlId) REFERENCES XmlParse(Id)
 );
+
+CREATE TABLE IF NOT EXISTS RowErrors (
+    Id INT UNSIGNED AUTO_INCREMENT NOT NULL,
+    XmlId INT UNSIGNED NOT NULL,
+    RowIndex INT UNSIGNED NOT NULL,
+    Line INT UNSIGNED,
+    ColumnNumber INT UNSIGNED,
+    Element VARCHAR(255),
+    Reason TEXT,
+    PRIMARY KEY(Id),
+    FOREIGN KEY (XmlId) REFERENCES XmlParse(Id)
+);
+
+CREATE TABLE IF NOT EXISTS HeldPayments (
+    Id INT UNSIGNED AUTO_INCREMENT NOT NULL,
+    XmlId INT UNSIGNED NOT NULL,
+    RowIndex INT UNSIGNED NOT NULL,
+    EmployeeId VARCHAR(255) NOT NULL,
+    PayorId VARCHAR(255) NOT NULL,
+    PayeeId VARCHAR(255) NOT NULL,
+    Amount INT UNSIGNED NOT NULL,
+    Reason TEXT,
+    Status VARCHAR(255) NOT NULL,
+    MethodId VARCHAR(255),
+    PRIMARY KEY(Id),
+    FOREIGN KEY (XmlId) REFERENCES XmlParse(Id),
+    FOREIGN KEY (EmployeeId) REFERENCES Employees(MethodId),
+    FOREIGN KEY (PayorId) REFERENCES Payors(MethodId),
+    FOREIGN KEY (PayeeId) REFERENCES Payees(MethodId)
+);
+
+CREATE TABLE IF NOT EXISTS TransactionRows (
+    Id INT UNSIGNED AUTO_INCREMENT NOT NULL,
+    XmlId INT UNSIGNED NOT NULL,
+    RowIndex INT UNSIGNED NOT NULL,
+    RawValues TEXT,
+    Status VARCHAR(255) NOT NULL,
+    EmployeeId VARCHAR(255),
+    PayorId VARCHAR(255),
+    PayeeId VARCHAR(255),
+    AddressId INT UNSIGNED,
+    PaymentId VARCHAR(255),
+    Error TEXT,
+    MethodError TEXT,
+    CreatedAt VARCHAR(255),
+    UpdatedAt VARCHAR(255),
+    PRIMARY KEY(Id),
+    UNIQUE (XmlId, RowIndex),
+    FOREIGN KEY (XmlId) REFERENCES XmlParse(Id)
+);
+
+CREATE TABLE IF NOT EXISTS Jobs (
+    Id INT UNSIGNED AUTO_INCREMENT NOT NULL,
+    Kind VARCHAR(255) NOT NULL,
+    XmlId INT UNSIGNED,
+    Status VARCHAR(255) NOT NULL,
+    Attempts INT UNSIGNED NOT NULL DEFAULT 0,
+    MaxAttempts INT UNSIGNED NOT NULL,
+    LeaseOwner VARCHAR(255),
+    LeaseExpiresAt BIGINT UNSIGNED,
+    RunAfter BIGINT UNSIGNED,
+    Error TEXT,
+    CreatedAt VARCHAR(255),
+    UpdatedAt VARCHAR(255),
+    PRIMARY KEY(Id),
+    INDEX (Status, LeaseExpiresAt),
+    FOREIGN KEY (XmlId) REFERENCES XmlParse(Id)
+);
+
+CREATE TABLE IF NOT EXISTS PaymentAttempts (
+    Id INT UNSIGNED AUTO_INCREMENT NOT NULL,
+    IdempotencyKey VARCHAR(255) NOT NULL,
+    XmlId INT UNSIGNED,
+    RowIndex INT UNSIGNED,
+    Status VARCHAR(255) NOT NULL,
+    PaymentId VARCHAR(255),
+    CreatedAt VARCHAR(255),
+    UpdatedAt VARCHAR(255),
+    PRIMARY KEY(Id),
+    UNIQUE (IdempotencyKey),
+    FOREIGN KEY (XmlId) REFERENCES XmlParse(Id)
+);
@@ -19,7 +19,7 @@ import MenuItem from "@mui/material/MenuItem";
 
 function Dashboard() {
     const [file, setFile] = useState(null);
-    const [postResponseData, setPostResponseData] = useState([]);
+    const [postResponseData, setPostResponseData] = useState({accepted: [], rejected: []});
     const [showParseWarning, setShowParseWarning] = useState(false);
     const [showServerWarning, setShowServerWarning] = useState(false);
     const [showPopup, setShowPopup] = useState(false);
@@ -47,7 +47,7 @@ function Dashboard() {
                 console.error('Error:', error);
             });
         // Check if data has changed and show the pop-up
-        if (postResponseData.length > 0) {
+        if (postResponseData.accepted.length > 0 || postResponseData.rejected.length > 0) {
             setShowPopup(true);
         }
     }, [postResponseData]);
@@ -319,17 +319,27 @@ function Dashboard() {
                     </CardContent>
                 </Card>
                 <Dialog open={showPopup} onClose={handleClosePopup}>
-                    <DialogTitle>Data Uploaded</DialogTitle>
+                    <DialogTitle>Upload Results</DialogTitle>
                     <DialogContent>
-                        {postResponseData.length > 0 && (
-                            <div>
+                        {postResponseData.accepted.map((xml, index) => (
+                            <div key={`accepted-${index}`}>
                                 <p>XML Uploaded Successfully</p>
-                                <p>id: {postResponseData[0].id}</p>
-                                <p>filename: {postResponseData[0].filename}</p>
-                                <p>status: {postResponseData[0].status}</p>
-                                <p>started_at: {postResponseData[0].started_at}</p>
+                                <p>id: {xml.id}</p>
+                                <p>filename: {xml.filename}</p>
+                                <p>status: {xml.status}</p>
+                                <p>started_at: {xml.started_at}</p>
                             </div>
-                        )}
+                        ))}
+                        {postResponseData.rejected.map((rejected, index) => (
+                            <Alert key={`rejected-${index}`} severity={rejected.duplicate_of ? "warning" : "error"} sx={{ marginTop: '0.5rem'}}>
+                                <p>{rejected.duplicate_of ? "Duplicate" : "Rejected"}: {rejected.filename}</p>
+                                {rejected.xml_id && <p>id: {rejected.xml_id}</p>}
+                                <p>reason: {rejected.reason}</p>
+                                {rejected.errors && rejected.errors.map((error, errorIndex) => (
+                                    <p key={errorIndex}>row {error.row_index}, line {error.line}: {error.reason}</p>
+                                ))}
+                            </Alert>
+                        ))}
                     </DialogContent>
                     <DialogActions>
                         <Button onClick={handleClosePopup}>Close</Button>